@@ -0,0 +1,218 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+/// Return a mask of the bits from `start` (inclusive) to `end` (exclusive) of an IPv6 address, counted from the
+/// most significant bit.
+fn bit_mask(start: u8, end: u8) -> u128 {
+    if start >= end {
+        return 0;
+    }
+    (u128::MAX >> start) & !u128::MAX.checked_shr(end.into()).unwrap_or(0)
+}
+
+/// Parse a subnet id written in hex, with or without the `0x` prefix.
+pub fn parse_subnet_id(subnet_id: &str) -> Result<u128, String> {
+    let digits = subnet_id.trim_start_matches("0x");
+    match u128::from_str_radix(digits, 16) {
+        Ok(subnet_id) => Ok(subnet_id),
+        Err(reason) => Err(format!(
+            "Subnet id {} is not a hex number: {}",
+            subnet_id, reason
+        )),
+    }
+}
+
+/// Compose the address written into an AAAA record from the acquired address.
+///
+/// The first `prefix_length` bits are the delegated prefix from `acquired`. The bits up to `subnet_prefix_length`
+/// are `subnet_id` and the rest are the last bits of `interface_id`. Parts that are not provided are kept from
+/// `acquired`.
+pub fn compose_ipv6(
+    acquired: Ipv6Addr,
+    prefix_length: u8,
+    subnet_prefix_length: u8,
+    subnet_id: Option<u128>,
+    interface_id: Option<Ipv6Addr>,
+) -> Result<Ipv6Addr, String> {
+    if subnet_prefix_length > 128 || prefix_length > subnet_prefix_length {
+        return Err(format!(
+            "prefix_length ({}) must not be larger than subnet_prefix_length ({}), which must not be larger than 128",
+            prefix_length, subnet_prefix_length
+        ));
+    }
+
+    let mut address = u128::from(acquired);
+
+    if let Some(subnet_id) = subnet_id {
+        let subnet_id_length = subnet_prefix_length - prefix_length;
+        if subnet_id_length == 0 {
+            return Err(format!(
+                "There is no room for the subnet id between prefix_length and subnet_prefix_length, both are {}",
+                prefix_length
+            ));
+        }
+        if subnet_id_length < 128 && subnet_id >> subnet_id_length != 0 {
+            return Err(format!(
+                "Subnet id {:#x} does not fit in the {} bits between prefix_length and subnet_prefix_length",
+                subnet_id, subnet_id_length
+            ));
+        }
+        let mask = bit_mask(prefix_length, subnet_prefix_length);
+        address = (address & !mask) | ((subnet_id << (128 - subnet_prefix_length)) & mask);
+    }
+
+    if let Some(interface_id) = interface_id {
+        let mask = bit_mask(subnet_prefix_length, 128);
+        let interface_id = u128::from(interface_id);
+        if interface_id & !mask != 0 {
+            log::warn!(
+                "The first {} bits of the interface id are not 0. They are ignored.",
+                subnet_prefix_length
+            );
+        }
+        address = (address & !mask) | (interface_id & mask);
+    }
+
+    Ok(address.into())
+}
+
+/// Parse a MAC address like `52:54:00:12:34:56`. `-` can also be used as the separator.
+pub fn parse_mac_address(mac_address: &str) -> Result<[u8; 6], String> {
+    let octets: Vec<u8> = match mac_address
+        .split([':', '-'])
+        .map(|octet| u8::from_str_radix(octet, 16))
+        .collect()
+    {
+        Ok(octets) => octets,
+        Err(reason) => {
+            return Err(format!(
+                "MAC address {} cannot be parsed: {}",
+                mac_address, reason
+            ))
+        }
+    };
+    match octets.try_into() {
+        Ok(octets) => Ok(octets),
+        Err(_) => Err(format!(
+            "MAC address {} does not have 6 octets",
+            mac_address
+        )),
+    }
+}
+
+/// Compute the modified EUI-64 interface id of a MAC address, as used by SLAAC without privacy extensions.
+pub fn eui64_interface_id(mac_address: &str) -> Result<Ipv6Addr, String> {
+    let octets = parse_mac_address(mac_address)?;
+
+    // Insert ff:fe in the middle and flip the universal/local bit.
+    let mut interface_id = [0u8; 16];
+    interface_id[8..11].copy_from_slice(&octets[..3]);
+    interface_id[11..13].copy_from_slice(&[0xff, 0xfe]);
+    interface_id[13..].copy_from_slice(&octets[3..]);
+    interface_id[8] ^= 0x02;
+    Ok(interface_id.into())
+}
+
+/// Return the name of the PTR record of `address`, e.g. `1.2.0.192.in-addr.arpa` for `192.0.2.1`.
+pub fn reverse_name(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, c, d] = address.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(address) => {
+            let bits = u128::from(address);
+            let mut name = String::new();
+            for nibble in 0..32 {
+                name.push_str(&format!("{:x}.", (bits >> (nibble * 4)) & 0xf));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v6(address: &str) -> Ipv6Addr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_subnet_id_with_and_without_prefix() {
+        assert_eq!(parse_subnet_id("0x1f"), Ok(0x1f));
+        assert_eq!(parse_subnet_id("1f"), Ok(0x1f));
+        assert!(parse_subnet_id("0xg").is_err());
+    }
+
+    #[test]
+    fn compose_ipv6_with_subnet_id_and_interface_id() {
+        let composed = compose_ipv6(
+            v6("2001:db8:1234:5600:1:2:3:4"),
+            56,
+            64,
+            Some(0x7),
+            Some(v6("::a:b:c:d")),
+        );
+        assert_eq!(composed, Ok(v6("2001:db8:1234:5607:a:b:c:d")));
+    }
+
+    #[test]
+    fn compose_ipv6_keeps_parts_not_provided() {
+        let acquired = v6("2001:db8:1234:5600:1:2:3:4");
+        assert_eq!(compose_ipv6(acquired, 56, 64, None, None), Ok(acquired));
+        assert_eq!(
+            compose_ipv6(acquired, 56, 64, None, Some(v6("::1"))),
+            Ok(v6("2001:db8:1234:5600::1"))
+        );
+        assert_eq!(
+            compose_ipv6(acquired, 56, 64, Some(0xff), None),
+            Ok(v6("2001:db8:1234:56ff:1:2:3:4"))
+        );
+    }
+
+    #[test]
+    fn compose_ipv6_rejects_subnet_id_out_of_room() {
+        let acquired = v6("2001:db8:1234:5600::1");
+        // 8 bits between /56 and /64.
+        assert!(compose_ipv6(acquired, 56, 64, Some(0x100), None).is_err());
+        // No bits between /64 and /64.
+        assert!(compose_ipv6(acquired, 64, 64, Some(0x1), None).is_err());
+        assert!(compose_ipv6(acquired, 64, 56, None, None).is_err());
+        assert!(compose_ipv6(acquired, 64, 129, None, None).is_err());
+    }
+
+    #[test]
+    fn eui64_interface_id_of_mac_address() {
+        // The construction of RFC 4291 appendix A: ff:fe is inserted in the middle and the universal/local bit of
+        // the first octet is inverted.
+        assert_eq!(
+            eui64_interface_id("00:aa:00:3f:2a:1c"),
+            Ok(v6("::2aa:ff:fe3f:2a1c"))
+        );
+        assert_eq!(
+            eui64_interface_id("02-AA-00-3F-2A-1C"),
+            Ok(v6("::aa:ff:fe3f:2a1c"))
+        );
+    }
+
+    #[test]
+    fn parse_mac_address_rejects_malformed() {
+        assert!(parse_mac_address("00:aa:00:3f:2a").is_err());
+        assert!(parse_mac_address("00:aa:00:3f:2a:1c:00").is_err());
+        assert!(parse_mac_address("00:aa:00:3f:2a:zz").is_err());
+    }
+
+    #[test]
+    fn reverse_name_of_ipv4_and_ipv6() {
+        assert_eq!(
+            reverse_name("192.0.2.5".parse().unwrap()),
+            "5.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+}
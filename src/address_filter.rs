@@ -0,0 +1,158 @@
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The shared address space used by carrier-grade NAT.
+pub const SHARED_ADDRESS_SPACE: Ipv4Net = Ipv4Net::new_assert(Ipv4Addr::new(100, 64, 0, 0), 10);
+
+const fn ipv4(range: Ipv4Net) -> IpNet {
+    IpNet::V4(range)
+}
+
+const fn ipv6(address: Ipv6Addr, prefix_length: u8) -> IpNet {
+    IpNet::V6(Ipv6Net::new_assert(address, prefix_length))
+}
+
+const fn v4(a: u8, b: u8, c: u8, d: u8, prefix_length: u8) -> IpNet {
+    ipv4(Ipv4Net::new_assert(
+        Ipv4Addr::new(a, b, c, d),
+        prefix_length,
+    ))
+}
+
+/// Ranges of addresses that are not reachable from the internet, so they should never be published by default,
+/// with a description for the log.
+const SPECIAL_RANGES: [(IpNet, &str); 24] = [
+    (v4(0, 0, 0, 0, 8), "this network"),
+    (v4(10, 0, 0, 0, 8), "private network"),
+    (ipv4(SHARED_ADDRESS_SPACE), "carrier-grade NAT"),
+    (v4(127, 0, 0, 0, 8), "loopback"),
+    (v4(169, 254, 0, 0, 16), "link-local"),
+    (v4(172, 16, 0, 0, 12), "private network"),
+    (v4(192, 0, 0, 0, 24), "IETF protocol assignments"),
+    (v4(192, 0, 2, 0, 24), "documentation"),
+    (v4(192, 168, 0, 0, 16), "private network"),
+    (v4(198, 18, 0, 0, 15), "benchmarking"),
+    (v4(198, 51, 100, 0, 24), "documentation"),
+    (v4(203, 0, 113, 0, 24), "documentation"),
+    (v4(224, 0, 0, 0, 4), "multicast"),
+    (v4(240, 0, 0, 0, 4), "reserved"),
+    (ipv6(Ipv6Addr::UNSPECIFIED, 128), "unspecified"),
+    (ipv6(Ipv6Addr::LOCALHOST, 128), "loopback"),
+    (
+        ipv6(Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0), 96),
+        "IPv4-mapped",
+    ),
+    (
+        ipv6(Ipv6Addr::new(0x100, 0, 0, 0, 0, 0, 0, 0), 64),
+        "discard-only",
+    ),
+    (
+        ipv6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0), 32),
+        "documentation",
+    ),
+    (
+        ipv6(Ipv6Addr::new(0x3fff, 0, 0, 0, 0, 0, 0, 0), 20),
+        "documentation",
+    ),
+    (
+        ipv6(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7),
+        "unique local",
+    ),
+    (
+        ipv6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10),
+        "link-local",
+    ),
+    (
+        ipv6(Ipv6Addr::new(0xfec0, 0, 0, 0, 0, 0, 0, 0), 10),
+        "site-local",
+    ),
+    (
+        ipv6(Ipv6Addr::new(0xff00, 0, 0, 0, 0, 0, 0, 0), 8),
+        "multicast",
+    ),
+];
+
+/// Return the range of [`SPECIAL_RANGES`] `address` is in, with its description.
+pub fn special_range(address: IpAddr) -> Option<(IpNet, &'static str)> {
+    SPECIAL_RANGES
+        .into_iter()
+        .find(|(range, _)| range.contains(&address))
+}
+
+/// Whether `address` is reachable from the internet, i.e. not in any of [`SPECIAL_RANGES`].
+pub fn is_global(address: IpAddr) -> bool {
+    special_range(address).is_none()
+}
+
+/// Decides whether an address may be published. Should be deserialized from the settings of a domain.
+///
+/// An address in `denied_ranges` is always rejected. Otherwise, an address in `allowed_ranges` is always
+/// accepted, which lets zones that are meant to hold private addresses opt in. Otherwise, addresses in
+/// [`SPECIAL_RANGES`] are rejected and the rest is accepted.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AddressFilter {
+    #[serde(default)]
+    pub allowed_ranges: Vec<IpNet>,
+    #[serde(default)]
+    pub denied_ranges: Vec<IpNet>,
+}
+
+impl AddressFilter {
+    /// Return `Ok(())` if `address` may be published. Otherwise, return a string specify the reason.
+    pub fn check(&self, address: IpAddr) -> Result<(), String> {
+        if let Some(range) = self
+            .denied_ranges
+            .iter()
+            .find(|range| range.contains(&address))
+        {
+            return Err(format!("{} is in denied range {}", address, range));
+        }
+
+        if let Some(range) = self
+            .allowed_ranges
+            .iter()
+            .find(|range| range.contains(&address))
+        {
+            log::trace!("{} is in allowed range {}", address, range);
+            return Ok(());
+        }
+
+        match special_range(address) {
+            Some((range, description)) => Err(format!(
+                "{} is in {} range {}, add it to allowed_ranges to publish it anyway",
+                address, description, range
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_ranges() {
+        assert_eq!(
+            special_range("100.64.1.1".parse().unwrap()),
+            Some((ipv4(SHARED_ADDRESS_SPACE), "carrier-grade NAT"))
+        );
+        assert!(!is_global("fd00::1".parse().unwrap()));
+        assert!(!is_global("2001:db8::1".parse().unwrap()));
+        assert!(is_global("100.128.0.1".parse().unwrap()));
+        assert!(is_global("2606:4700::1111".parse().unwrap()));
+    }
+
+    #[test]
+    fn denied_ranges_win_over_allowed_ranges() {
+        let filter = AddressFilter {
+            allowed_ranges: vec!["192.168.0.0/16".parse().unwrap()],
+            denied_ranges: vec!["192.168.1.0/24".parse().unwrap()],
+        };
+        assert!(filter.check("192.168.2.1".parse().unwrap()).is_ok());
+        assert!(filter.check("192.168.1.1".parse().unwrap()).is_err());
+        assert!(filter.check("10.0.0.1".parse().unwrap()).is_err());
+        assert!(filter.check("1.1.1.1".parse().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,174 @@
+use ipnet::{IpNet, Ipv4Net};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A rule rewriting the acquired address into the address reachable from outside, for sites translating
+/// addresses at the edge. Should be deserialized from the settings of a domain or a subdomain.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MappingRule {
+    /// Replace the prefix `from` of an address with `to` and keep the rest of the bits, like NPTv6 done by
+    /// NETMAP or IPv4 1:1 NAT of a whole network. Both prefixes must be of the same length.
+    Prefix { from: IpNet, to: IpNet },
+    /// Replace an IPv4 address with the address `offset` after the start of its network of `prefix_length`,
+    /// e.g. a host behind 1:1 NAT in the block of the detected address of the router.
+    Ipv4Offset { prefix_length: u8, offset: u32 },
+}
+
+impl MappingRule {
+    /// Return the mapped address if this rule applies to `address`, or `None` if not. If the rule cannot be
+    /// applied, return a string specify the reason.
+    pub fn map(&self, address: IpAddr) -> Result<Option<IpAddr>, String> {
+        match self {
+            MappingRule::Prefix { from, to } => {
+                if !from.contains(&address) {
+                    return Ok(None);
+                }
+                match (address, from, to) {
+                    (IpAddr::V4(address), IpNet::V4(from), IpNet::V4(to))
+                        if from.prefix_len() == to.prefix_len() =>
+                    {
+                        let mask = u32::from(to.netmask());
+                        let mapped = (u32::from(address) & !mask) | u32::from(to.network());
+                        Ok(Some(Ipv4Addr::from(mapped).into()))
+                    }
+                    (IpAddr::V6(address), IpNet::V6(from), IpNet::V6(to))
+                        if from.prefix_len() == to.prefix_len() =>
+                    {
+                        let mask = u128::from(to.netmask());
+                        let mapped = (u128::from(address) & !mask) | u128::from(to.network());
+                        Ok(Some(Ipv6Addr::from(mapped).into()))
+                    }
+                    _ => Err(format!(
+                        "Prefixes {} and {} are not of the same family and length",
+                        from, to
+                    )),
+                }
+            }
+            MappingRule::Ipv4Offset {
+                prefix_length,
+                offset,
+            } => {
+                let IpAddr::V4(address) = address else {
+                    return Ok(None);
+                };
+                let network = match Ipv4Net::new(address, *prefix_length) {
+                    Ok(network) => network.trunc(),
+                    Err(reason) => {
+                        return Err(format!(
+                            "Prefix length {} is invalid: {}",
+                            prefix_length, reason
+                        ))
+                    }
+                };
+                let host_bits = 32 - u32::from(*prefix_length);
+                if host_bits < 32 && offset >> host_bits != 0 {
+                    return Err(format!("Offset {} is out of network {}", offset, network));
+                }
+                let mapped = u32::from(network.network()) | offset;
+                Ok(Some(Ipv4Addr::from(mapped).into()))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for MappingRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MappingRule::Prefix { from, to } => write!(f, "prefix {} to {}", from, to),
+            MappingRule::Ipv4Offset {
+                prefix_length,
+                offset,
+            } => write!(f, "offset {} in /{}", offset, prefix_length),
+        }
+    }
+}
+
+/// Apply the first rule in `rules` that applies to `address`. If no rule applies, `address` is returned as is.
+pub fn map_address<'a>(
+    rules: impl IntoIterator<Item = &'a MappingRule>,
+    address: IpAddr,
+) -> Result<IpAddr, String> {
+    for rule in rules {
+        match rule.map(address) {
+            Ok(Some(mapped)) => {
+                log::trace!("Mapped {} to {} with rule {}", address, mapped, rule);
+                return Ok(mapped);
+            }
+            Ok(None) => continue,
+            Err(reason) => return Err(format!("Mapping rule {} failed: {}", rule, reason)),
+        }
+    }
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(from: &str, to: &str) -> MappingRule {
+        MappingRule::Prefix {
+            from: from.parse().unwrap(),
+            to: to.parse().unwrap(),
+        }
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn prefix_rule_keeps_the_host_bits() {
+        let rule = prefix("fd00:1::/48", "2001:db8:1::/48");
+        assert_eq!(
+            rule.map(ip("fd00:1:0:2::3")),
+            Ok(Some(ip("2001:db8:1:2::3")))
+        );
+        let rule = prefix("192.168.1.0/24", "198.51.100.0/24");
+        assert_eq!(rule.map(ip("192.168.1.7")), Ok(Some(ip("198.51.100.7"))));
+    }
+
+    #[test]
+    fn prefix_rule_skips_addresses_outside_from() {
+        let rule = prefix("fd00:1::/48", "2001:db8:1::/48");
+        assert_eq!(rule.map(ip("fd00:2::1")), Ok(None));
+        assert_eq!(rule.map(ip("192.168.1.7")), Ok(None));
+    }
+
+    #[test]
+    fn prefix_rule_needs_prefixes_of_the_same_length() {
+        let rule = prefix("fd00:1::/48", "2001:db8::/32");
+        assert!(rule.map(ip("fd00:1::1")).is_err());
+        let rule = prefix("192.168.1.0/24", "2001:db8::/24");
+        assert!(rule.map(ip("192.168.1.7")).is_err());
+    }
+
+    #[test]
+    fn ipv4_offset_rule() {
+        let rule = MappingRule::Ipv4Offset {
+            prefix_length: 29,
+            offset: 3,
+        };
+        assert_eq!(rule.map(ip("203.0.113.9")), Ok(Some(ip("203.0.113.11"))));
+        assert_eq!(rule.map(ip("2001:db8::1")), Ok(None));
+        let rule = MappingRule::Ipv4Offset {
+            prefix_length: 29,
+            offset: 8,
+        };
+        assert!(rule.map(ip("203.0.113.9")).is_err());
+    }
+
+    #[test]
+    fn map_address_uses_the_first_rule_that_applies() {
+        let rules = [
+            prefix("10.0.0.0/8", "100.0.0.0/8"),
+            prefix("192.168.1.0/24", "198.51.100.0/24"),
+            prefix("192.168.0.0/16", "203.0.0.0/16"),
+        ];
+        assert_eq!(
+            map_address(&rules, ip("192.168.1.7")),
+            Ok(ip("198.51.100.7"))
+        );
+        assert_eq!(map_address(&rules, ip("172.16.0.1")), Ok(ip("172.16.0.1")));
+    }
+}
@@ -5,21 +5,47 @@ pub mod request {
     #[derive(Serialize)]
     pub struct CreateRecord {
         pub name: String,
-        pub content: String,
+        // Records with structured content, e.g. HTTPS, use data instead.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub content: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub data: Option<ServiceData>,
         #[serde(rename = "type")]
         pub record_type: RecordType,
-        pub proxied: bool,
+        // Only A, AAAA and CNAME records can be proxied.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub proxied: Option<bool>,
         pub ttl: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub comment: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub tags: Option<Vec<String>>,
     }
 
     #[derive(Serialize)]
     pub struct UpdateRecord {
         pub name: String,
-        pub content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub content: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub data: Option<ServiceData>,
         #[serde(rename = "type")]
         pub record_type: RecordType,
-        pub proxied: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub proxied: Option<bool>,
         pub ttl: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub comment: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub tags: Option<Vec<String>>,
+    }
+
+    /// The data of an HTTPS or SVCB record.
+    #[derive(Serialize)]
+    pub struct ServiceData {
+        pub priority: u16,
+        pub target: String,
+        pub value: String,
     }
 }
 
@@ -65,6 +91,104 @@ pub mod response {
         pub proxiable: bool,
         pub tags: Vec<String>,
         pub ttl: u32,
+        // The structured content of some types of records, e.g. HTTPS. Its fields depend on the type.
+        pub data: Option<serde_json::Value>,
+    }
+
+    /// The data of an HTTPS or SVCB record.
+    #[derive(Deserialize, Debug)]
+    pub struct ServiceData {
+        pub priority: u16,
+        pub target: String,
+        pub value: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct TokenStatus {
+        pub id: String,
+        /// One of `active`, `disabled` and `expired`.
+        pub status: String,
+        pub expires_on: Option<String>,
+        pub not_before: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct VerifyToken {
+        // if success is false, the result may be null!
+        pub result: Option<TokenStatus>,
+        pub errors: Vec<CodeMessagePair>,
+        pub messages: Vec<CodeMessagePair>,
+        pub success: bool,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct PermissionGroup {
+        pub id: String,
+        pub name: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct Policy {
+        /// Either `allow` or `deny`.
+        pub effect: String,
+        pub permission_groups: Vec<PermissionGroup>,
+        // Resource names mapped to `*` or to nested resources, e.g. the zones of an account.
+        pub resources: serde_json::Value,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct Token {
+        pub id: String,
+        pub policies: Vec<Policy>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct TokenDetail {
+        // if success is false, the result may be null!
+        pub result: Option<Token>,
+        pub errors: Vec<CodeMessagePair>,
+        pub messages: Vec<CodeMessagePair>,
+        pub success: bool,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct User {
+        pub id: String,
+        pub email: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct UserDetail {
+        // if success is false, the result may be null!
+        pub result: Option<User>,
+        pub errors: Vec<CodeMessagePair>,
+        pub messages: Vec<CodeMessagePair>,
+        pub success: bool,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct Zone {
+        pub id: String,
+        pub name: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct ListZones {
+        // if success is false, the result may be null!
+        pub result: Option<Vec<Zone>>,
+        pub errors: Vec<CodeMessagePair>,
+        pub messages: Vec<CodeMessagePair>,
+        pub success: bool,
     }
 
     #[derive(Deserialize, Debug)]
@@ -97,6 +221,22 @@ pub mod response {
         pub success: bool,
     }
 
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct DeletedRecord {
+        pub id: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    pub struct DeleteRecord {
+        // if success is false, the result may be null!
+        pub result: Option<DeletedRecord>,
+        pub errors: Vec<CodeMessagePair>,
+        pub messages: Vec<CodeMessagePair>,
+        pub success: bool,
+    }
+
     impl std::fmt::Display for CodeMessagePair {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             write!(f, "Code {}: {}", self.code, self.message)
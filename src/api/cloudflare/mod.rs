@@ -1,17 +1,89 @@
 mod api;
 
 use api::{request, response};
-use serde::Deserialize;
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
-use super::ApiInterface;
+use super::{full_domain_name, ApiInterface};
 
-use crate::api::RecordDetail;
+use crate::api::{RecordContent, RecordDetail, ServiceData};
+use crate::state::lock;
+use crate::RecordType;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct CloudflareInterfaceBuildConfig {
-    zone_id: String,
-    api_token: String,
+    /// Looked up from the domain name when not provided.
+    zone_id: Option<String>,
+    #[serde(flatten)]
+    auth: CloudflareAuth,
+    /// Warn when the api token expires within this many days. 0 disables the warning.
+    #[serde(default = "default_expiry_warning_days")]
+    expiry_warning_days: u32,
+    /// The url every api path is appended to, e.g. to go through a gateway.
+    #[serde(default = "default_base_url")]
+    base_url: String,
+}
+
+/// How requests to the api are authenticated. Written in the config file as either `api_token`, or `email` and
+/// `api_key`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "CloudflareAuthSetting")]
+pub enum CloudflareAuth {
+    /// A scoped api token, sent as a bearer token.
+    Token { api_token: String },
+    /// The legacy global api key of an account, sent with the email of the account.
+    GlobalKey { email: String, api_key: String },
+}
+
+#[derive(Deserialize)]
+struct CloudflareAuthSetting {
+    api_token: Option<String>,
+    email: Option<String>,
+    api_key: Option<String>,
+}
+
+impl TryFrom<CloudflareAuthSetting> for CloudflareAuth {
+    type Error = String;
+
+    fn try_from(value: CloudflareAuthSetting) -> Result<Self, Self::Error> {
+        match (value.api_token, value.email, value.api_key) {
+            (Some(api_token), None, None) => Ok(CloudflareAuth::Token { api_token }),
+            (None, Some(email), Some(api_key)) => Ok(CloudflareAuth::GlobalKey { email, api_key }),
+            _ => Err("Either api_token, or both email and api_key are needed".to_string()),
+        }
+    }
+}
+
+fn default_expiry_warning_days() -> u32 {
+    14
+}
+
+fn default_base_url() -> String {
+    "https://api.cloudflare.com/client/v4".to_string()
+}
+
+/// The id of the permission group allowing to edit DNS records, the same for every account. A permission group
+/// named `DNS Write` is also accepted.
+const DNS_WRITE_PERMISSION_GROUP: &str = "4755a26eedb94da69e1066d98aa820be";
+
+/// What `authorize` found out in a run, shared by the interfaces created with it so that every api token and zone is
+/// only checked once. Only successful checks are kept, and the lock is not held during requests, so interfaces
+/// checking the same token at the same time may both send the requests.
+#[derive(Default)]
+pub struct AuthorizeCache {
+    results: Mutex<AuthorizeResults>,
+}
+
+#[derive(Default)]
+struct AuthorizeResults {
+    /// The id of every verified api token, `None` for global api keys. By the secret.
+    credentials: HashMap<String, Option<String>>,
+    /// The zone id of every domain, by the secret and the domain name.
+    zone_ids: HashMap<(String, String), String>,
+    /// The secrets and zone ids whose DNS permission is checked.
+    permissions: HashSet<(String, String)>,
 }
 
 // Currently, the build config is exactely the config needed to be stored.
@@ -20,15 +92,330 @@ type CloudflareInterfaceConfig = CloudflareInterfaceBuildConfig;
 pub struct CloudflareInterface {
     config: CloudflareInterfaceConfig,
     reqwest_client: reqwest::blocking::Client,
-    record_id_cache: HashMap<String, String>,
+    authorize_cache: Arc<AuthorizeCache>,
 }
 
 impl CloudflareInterface {
-    fn get_full_domain(subdomain: &str, base_domain: &str) -> String {
-        if subdomain == "@" || subdomain.is_empty() {
-            base_domain.to_string()
+    /// Share the results of `authorize` with the other interfaces of the run using `authorize_cache`.
+    pub fn with_authorize_cache(mut self, authorize_cache: Arc<AuthorizeCache>) -> Self {
+        self.authorize_cache = authorize_cache;
+        self
+    }
+
+    /// The id of the zone, either configured or looked up by `authorize`.
+    fn zone_id(&self) -> Result<&str, String> {
+        match &self.config.zone_id {
+            Some(zone_id) => Ok(zone_id),
+            None => Err("Zone id is not resolved".to_string()),
+        }
+    }
+
+    /// A hint to tell which credential is used in messages, without revealing it.
+    fn auth_hint(&self) -> String {
+        match &self.config.auth {
+            CloudflareAuth::Token { api_token } => match api_token.char_indices().rev().nth(3) {
+                Some((index, _)) if api_token.len() > 8 => {
+                    format!("api token ending in {}", &api_token[index..])
+                }
+                _ => "api token".to_string(),
+            },
+            CloudflareAuth::GlobalKey { email, .. } => format!("global api key of {}", email),
+        }
+    }
+
+    /// The secret of the credential and the api it is sent to, identifying the credential in the cache.
+    fn secret(&self) -> String {
+        let secret = match &self.config.auth {
+            CloudflareAuth::Token { api_token } => api_token,
+            CloudflareAuth::GlobalKey { api_key, .. } => api_key,
+        };
+        format!("{} {}", self.config.base_url, secret)
+    }
+
+    /// The url of the api endpoint at `path`.
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.base_url.trim_end_matches('/'), path)
+    }
+
+    /// Add the headers authenticating `request` to the api.
+    fn authenticate(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::blocking::RequestBuilder {
+        match &self.config.auth {
+            CloudflareAuth::Token { api_token } => {
+                request.header("Authorization", format!("Bearer {}", api_token))
+            }
+            CloudflareAuth::GlobalKey { email, api_key } => request
+                .header("X-Auth-Email", email)
+                .header("X-Auth-Key", api_key),
+        }
+    }
+
+    /// Send a GET request to `url` and parse the returned json.
+    fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        let request = self.authenticate(self.reqwest_client.get(url));
+
+        let response = match request.send() {
+            Ok(response) => response,
+            Err(reason) => return Err(format!("Error sending https request: {}", reason)),
+        };
+
+        let response_text = match response.text() {
+            Ok(content) => content,
+            Err(reason) => return Err(format!("Error decoding response: {}", reason)),
+        };
+
+        log::trace!("GET {} returned text: {}", url, response_text);
+
+        match serde_json::from_str(&response_text) {
+            Ok(parsed_details) => Ok(parsed_details),
+            Err(reason) => Err(format!("Error parse json: {}", reason)),
+        }
+    }
+
+    /// Check that the credential can be used. Return the id of an api token, or `None` for a global api key.
+    fn verify_auth(&self) -> Result<Option<String>, String> {
+        match &self.config.auth {
+            CloudflareAuth::Token { .. } => self.verify_token().map(Some),
+            CloudflareAuth::GlobalKey { .. } => self.verify_global_key().map(|_| None),
+        }
+    }
+
+    /// Check that the global api key and the email are accepted by reading the user they belong to.
+    fn verify_global_key(&self) -> Result<(), String> {
+        let user_detail: response::UserDetail = self.get_json(&self.url("/user"))?;
+        match user_detail.result {
+            Some(user) if user_detail.success => {
+                log::debug!("The {} belongs to user {}", self.auth_hint(), user.id);
+                Ok(())
+            }
+            _ => Err(format!(
+                "Verify the {} failed: {}",
+                self.auth_hint(),
+                user_detail
+                    .errors
+                    .iter()
+                    .fold(String::new(), |prev_string, cur_pair| {
+                        format!("{}[{}]", prev_string, cur_pair)
+                    })
+            )),
+        }
+    }
+
+    /// Check that the api token is active and within its validity period, and return its id.
+    fn verify_token(&self) -> Result<String, String> {
+        let verify_result: response::VerifyToken =
+            self.get_json(&self.url("/user/tokens/verify"))?;
+        let token = match verify_result.result {
+            Some(token) if verify_result.success => token,
+            _ => {
+                return Err(format!(
+                    "Verify the {} failed: {}",
+                    self.auth_hint(),
+                    verify_result
+                        .errors
+                        .iter()
+                        .fold(String::new(), |prev_string, cur_pair| {
+                            format!("{}[{}]", prev_string, cur_pair)
+                        })
+                ))
+            }
+        };
+
+        if token.status != "active" {
+            return Err(format!("The {} is {}", self.auth_hint(), token.status));
+        }
+        let now = Utc::now();
+        let parse_time = |time: &Option<String>| -> Option<DateTime<Utc>> {
+            let time = time.as_deref()?;
+            match DateTime::parse_from_rfc3339(time) {
+                Ok(time) => Some(time.with_timezone(&Utc)),
+                Err(reason) => {
+                    log::warn!("Cannot parse time {} of the api token: {}", time, reason);
+                    None
+                }
+            }
+        };
+        if let Some(not_before) = parse_time(&token.not_before) {
+            if not_before > now {
+                return Err(format!(
+                    "The {} is not valid before {}",
+                    self.auth_hint(),
+                    not_before
+                ));
+            }
+        }
+        if let Some(expires_on) = parse_time(&token.expires_on) {
+            if expires_on <= now {
+                return Err(format!(
+                    "The {} expired on {}",
+                    self.auth_hint(),
+                    expires_on
+                ));
+            }
+            if expires_on - now < chrono::Duration::days(self.config.expiry_warning_days.into()) {
+                log::warn!(
+                    "The {} expires on {}. Roll it before then.",
+                    self.auth_hint(),
+                    expires_on
+                );
+            }
+        }
+        Ok(token.id)
+    }
+
+    /// Check that the api token of `token_id` can edit the DNS records of the zone. Reading the policies of a token
+    /// needs a permission most tokens do not have, in which case the check is skipped with a warning.
+    fn check_dns_permission(&self, token_id: &str) -> Result<(), String> {
+        let zone_id = self.zone_id()?;
+        let token_detail: response::TokenDetail =
+            match self.get_json(&self.url(&format!("/user/tokens/{}", token_id))) {
+                Ok(token_detail) => token_detail,
+                Err(reason) => {
+                    return Err(format!(
+                        "Read the policies of the {} failed: {}",
+                        self.auth_hint(),
+                        reason
+                    ))
+                }
+            };
+        let Some(token) = token_detail.result.filter(|_| token_detail.success) else {
+            log::warn!(
+                "The {} is not allowed to read its own policies, so its DNS Write permission on zone {} is not checked.",
+                self.auth_hint(),
+                zone_id
+            );
+            return Ok(());
+        };
+
+        let zone_resource = format!("com.cloudflare.api.account.zone.{}", zone_id);
+        if allows_dns_write(&token.policies, &zone_resource) {
+            Ok(())
         } else {
-            format!("{}.{}", subdomain, base_domain)
+            Err(format!(
+                "The {} does not have the DNS Write permission on zone {}",
+                self.auth_hint(),
+                zone_id
+            ))
+        }
+    }
+
+    /// Find the id of the zone holding `domain_name`, trying the domain and then its parents so that a subdomain
+    /// configured as a domain also works.
+    fn lookup_zone_id(&self, domain_name: &str) -> Result<String, String> {
+        let labels: Vec<&str> = domain_name.trim_end_matches('.').split('.').collect();
+        // A zone has at least two labels.
+        for start in 0..labels.len().saturating_sub(1) {
+            let zone_name = labels[start..].join(".");
+            log::trace!("Look up zone {}", zone_name);
+
+            let url = match reqwest::Url::parse_with_params(
+                &self.url("/zones"),
+                [("name", zone_name.as_str())],
+            ) {
+                Ok(result) => result,
+                Err(reason) => return Err(format!("Error parsing url with params: {}", reason)),
+            };
+            let zones: response::ListZones = self.get_json(url.as_str())?;
+
+            if !zones.success {
+                return Err(format!(
+                    "Look up zone of {} failed: {}. Make sure the {} has the Zone:Read permission, or set zone_id.",
+                    domain_name,
+                    zones
+                        .errors
+                        .iter()
+                        .fold(String::new(), |prev_string, cur_pair| {
+                            format!("{}[{}]", prev_string, cur_pair)
+                        }),
+                    self.auth_hint()
+                ));
+            }
+
+            if let Some(zone) = zones.result.into_iter().flatten().next() {
+                log::debug!("Found zone {} of {}: {}", zone.name, domain_name, zone.id);
+                return Ok(zone.id);
+            }
+        }
+        Err(format!(
+            "No zone holding {} is visible. Make sure the {} has the Zone:Read permission for it, or set zone_id.",
+            domain_name,
+            self.auth_hint()
+        ))
+    }
+
+    /// Split `record_content` into the `content`, `data` and `proxied` fields of a request body.
+    fn content_fields(
+        record_content: &RecordContent,
+        proxied: Option<bool>,
+    ) -> (Option<String>, Option<request::ServiceData>, Option<bool>) {
+        match record_content {
+            RecordContent::Address(address) => (
+                Some(address.to_string()),
+                None,
+                Some(proxied.unwrap_or(false)),
+            ),
+            RecordContent::Https(data) | RecordContent::Svcb(data) => (
+                None,
+                Some(request::ServiceData {
+                    priority: data.priority,
+                    target: data.target.clone(),
+                    value: data.params.clone(),
+                }),
+                None,
+            ),
+            RecordContent::Text(text) | RecordContent::Pointer(text) => {
+                (Some(text.clone()), None, None)
+            }
+        }
+    }
+
+    /// Read the content of a record returned by the api.
+    fn parse_content(record: &response::RecordDetail) -> Result<RecordContent, String> {
+        match record.record_type {
+            RecordType::A | RecordType::Aaaa => match record.content.parse() {
+                Ok(address) => Ok(RecordContent::Address(address)),
+                Err(reason) => Err(format!(
+                    "Pase api-returned address as IP address failed: {}",
+                    reason
+                )),
+            },
+            RecordType::Https | RecordType::Svcb => {
+                let data: response::ServiceData = match &record.data {
+                    Some(data) => match serde_json::from_value(data.clone()) {
+                        Ok(data) => data,
+                        Err(reason) => {
+                            return Err(format!(
+                                "Error parse {} record data: {}",
+                                record.record_type, reason
+                            ))
+                        }
+                    },
+                    None => return Err(format!("{} record has no data", record.record_type)),
+                };
+                let data = ServiceData {
+                    priority: data.priority,
+                    target: data.target,
+                    params: data.value,
+                };
+                Ok(match record.record_type {
+                    RecordType::Https => RecordContent::Https(data),
+                    _ => RecordContent::Svcb(data),
+                })
+            }
+            // The api may return the text quoted as in a zone file.
+            RecordType::Txt => Ok(RecordContent::Text(
+                match record
+                    .content
+                    .strip_prefix('"')
+                    .and_then(|text| text.strip_suffix('"'))
+                {
+                    Some(text) if !text.contains('"') => text.to_string(),
+                    _ => record.content.clone(),
+                },
+            )),
+            RecordType::Ptr => Ok(RecordContent::Pointer(record.content.clone())),
         }
     }
 }
@@ -39,29 +426,75 @@ impl ApiInterface for CloudflareInterface {
         Self {
             config,
             reqwest_client: reqwest::blocking::Client::new(),
-            record_id_cache: HashMap::new(),
+            authorize_cache: Arc::default(),
         }
     }
 
-    fn authorize(&mut self) -> Result<(), String> {
+    fn authorize(&mut self, base_domain_name: &str) -> Result<(), String> {
+        let secret = self.secret();
+
+        let cached = lock(&self.authorize_cache.results)
+            .credentials
+            .get(&secret)
+            .cloned();
+        let token_id = match cached {
+            Some(token_id) => token_id,
+            None => {
+                let token_id = self.verify_auth()?;
+                lock(&self.authorize_cache.results)
+                    .credentials
+                    .insert(secret.clone(), token_id.clone());
+                token_id
+            }
+        };
+
+        if self.config.zone_id.is_none() {
+            let key = (secret.clone(), base_domain_name.to_string());
+            let cached = lock(&self.authorize_cache.results)
+                .zone_ids
+                .get(&key)
+                .cloned();
+            let zone_id = match cached {
+                Some(zone_id) => zone_id,
+                None => {
+                    let zone_id = self.lookup_zone_id(base_domain_name)?;
+                    lock(&self.authorize_cache.results)
+                        .zone_ids
+                        .insert(key, zone_id.clone());
+                    zone_id
+                }
+            };
+            self.config.zone_id = Some(zone_id);
+        }
+
+        // A global api key can do anything its account can.
+        let Some(token_id) = token_id else {
+            return Ok(());
+        };
+        let key = (secret, self.zone_id()?.to_string());
+        if lock(&self.authorize_cache.results)
+            .permissions
+            .contains(&key)
+        {
+            return Ok(());
+        }
+        self.check_dns_permission(&token_id)?;
+        lock(&self.authorize_cache.results).permissions.insert(key);
         Ok(())
     }
 
-    fn describe_record(
+    fn describe_records(
         &mut self,
         subdomain_name: &str,
         base_domain_name: &str,
         record_type: crate::RecordType,
-    ) -> Result<Option<super::RecordDetail>, String> {
-        log::trace!("Cloudflare api interface describe_record");
-        let full_domain_name = Self::get_full_domain(subdomain_name, base_domain_name);
+    ) -> Result<Vec<super::RecordDetail>, String> {
+        log::trace!("Cloudflare api interface describe_records");
+        let full_domain_name = full_domain_name(subdomain_name, base_domain_name);
         log::trace!("Full domain name: {}", full_domain_name);
 
         // Create the url for send, including the get params
-        let url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{}/dns_records",
-            self.config.zone_id
-        );
+        let url = self.url(&format!("/zones/{}/dns_records", self.zone_id()?));
         let params = [
             ("name", full_domain_name.clone()),
             ("type", format!("{}", record_type)),
@@ -76,10 +509,7 @@ impl ApiInterface for CloudflareInterface {
         log::trace!("Encoded url: {}", url);
 
         // Create the request with authorization
-        let request = self
-            .reqwest_client
-            .get(url)
-            .header("Authorization", format!("Bearer {}", self.config.api_token));
+        let request = self.authenticate(self.reqwest_client.get(url));
 
         // Get response and decode into struct
         log::trace!("Send describe request");
@@ -113,52 +543,44 @@ impl ApiInterface for CloudflareInterface {
         }
 
         // Now the api request should be successful
-        match record_detail.result.len() {
-            0 => Ok(None),
-            1 => {
-                self.record_id_cache
-                    .insert(full_domain_name, record_detail.result[0].id.clone());
-                Ok(Some(RecordDetail {
+        record_detail
+            .result
+            .iter()
+            .map(|record| {
+                Ok(RecordDetail {
+                    id: Some(record.id.clone()),
                     subdomain_name: subdomain_name.to_string(),
                     base_domain_name: base_domain_name.to_string(),
-                    ttl: record_detail.result[0].ttl,
-                    proxied: record_detail.result[0].proxied,
-                    ip_addr: match record_detail.result[0].content.parse() {
-                        Ok(address) => address,
-                        Err(reason) => {
-                            return Err(format!(
-                                "Pase api-returned address as IP address failed: {}",
-                                reason
-                            ))
-                        }
-                    },
-                }))
-            }
-            _ => Err(format!(
-                "More than 1 records exists for {} record of {}.",
-                record_type, full_domain_name
-            )),
-        }
+                    ttl: record.ttl,
+                    proxied: record.proxied,
+                    content: Self::parse_content(record)?,
+                    comment: record.comment.clone().filter(|comment| !comment.is_empty()),
+                    tags: Some(record.tags.clone()),
+                })
+            })
+            .collect()
     }
 
     fn create_record(&self, record_content: RecordDetail) -> Result<(), String> {
         log::trace!("Cloudflare api create_record");
-        let full_domain_name = Self::get_full_domain(
+        let full_domain_name = full_domain_name(
             &record_content.subdomain_name,
             &record_content.base_domain_name,
         );
 
-        let url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{}/dns_records",
-            self.config.zone_id
-        );
+        let url = self.url(&format!("/zones/{}/dns_records", self.zone_id()?));
 
+        let (content, data, proxied) =
+            Self::content_fields(&record_content.content, record_content.proxied);
         let post_body = request::CreateRecord {
             name: full_domain_name.clone(),
-            content: record_content.ip_addr.to_string(),
-            record_type: record_content.ip_addr.into(),
-            proxied: record_content.proxied.unwrap_or(false),
+            content,
+            data,
+            record_type: record_content.content.record_type(),
+            proxied,
             ttl: record_content.ttl,
+            comment: record_content.comment,
+            tags: record_content.tags,
         };
 
         let post_body = match serde_json::to_string(&post_body) {
@@ -169,9 +591,7 @@ impl ApiInterface for CloudflareInterface {
         log::trace!("Create record post body serialized text: {}", post_body);
 
         let request = self
-            .reqwest_client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", self.config.api_token))
+            .authenticate(self.reqwest_client.post(url))
             .header("Content-Type", "application/json")
             .body(post_body);
 
@@ -221,27 +641,33 @@ impl ApiInterface for CloudflareInterface {
         log::trace!("Cloudflare api update_record");
 
         // The cloudflare api needs record id to update
-        let full_domain_name = Self::get_full_domain(
+        let full_domain_name = full_domain_name(
             &record_content.subdomain_name,
             &record_content.base_domain_name,
         );
 
-        let record_id = match self.record_id_cache.get(&full_domain_name) {
+        let record_id = match &record_content.id {
             Some(id) => id,
-            None => return Err(format!("No record id cache for {}", full_domain_name)),
+            None => return Err(format!("No record id for {}", full_domain_name)),
         };
 
-        let url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
-            self.config.zone_id, record_id
-        );
+        let url = self.url(&format!(
+            "/zones/{}/dns_records/{}",
+            self.zone_id()?,
+            record_id
+        ));
 
+        let (content, data, proxied) =
+            Self::content_fields(&record_content.content, record_content.proxied);
         let put_body = request::UpdateRecord {
             name: full_domain_name,
-            content: record_content.ip_addr.to_string(),
-            record_type: record_content.ip_addr.into(),
-            proxied: record_content.proxied.unwrap_or(false),
+            content,
+            data,
+            record_type: record_content.content.record_type(),
+            proxied,
             ttl: record_content.ttl,
+            comment: record_content.comment,
+            tags: record_content.tags,
         };
 
         let put_body = match serde_json::to_string(&put_body) {
@@ -250,9 +676,7 @@ impl ApiInterface for CloudflareInterface {
         };
 
         let request = self
-            .reqwest_client
-            .put(url)
-            .header("Authorization", format!("Bearer {}", self.config.api_token))
+            .authenticate(self.reqwest_client.put(url))
             .body(put_body);
 
         let response = match request.send() {
@@ -291,4 +715,175 @@ impl ApiInterface for CloudflareInterface {
 
         Ok(())
     }
+
+    fn delete_record(&self, record_detail: &RecordDetail) -> Result<(), String> {
+        log::trace!("Cloudflare api delete_record");
+
+        // The cloudflare api needs record id to delete
+        let record_id = match &record_detail.id {
+            Some(id) => id,
+            None => {
+                return Err(format!(
+                    "No record id for {}",
+                    full_domain_name(
+                        &record_detail.subdomain_name,
+                        &record_detail.base_domain_name
+                    )
+                ))
+            }
+        };
+
+        let url = self.url(&format!(
+            "/zones/{}/dns_records/{}",
+            self.zone_id()?,
+            record_id
+        ));
+
+        let request = self.authenticate(self.reqwest_client.delete(url));
+
+        let response = match request.send() {
+            Ok(response) => response,
+            Err(reason) => return Err(format!("Failed to send delete record request: {}", reason)),
+        };
+
+        let response_text = match response.text() {
+            Ok(content) => content,
+            Err(reason) => return Err(format!("Error decoding response: {}", reason)),
+        };
+
+        log::trace!("Delete record returned text: {}", response_text);
+
+        let delete_result: response::DeleteRecord = match serde_json::from_str(&response_text) {
+            Ok(parsed_details) => parsed_details,
+            Err(reason) => {
+                return Err(format!(
+                    "Failed to parse returned request as json: {}",
+                    reason
+                ))
+            }
+        };
+
+        if !delete_result.success {
+            return Err(format!(
+                "Delete record api failed: {}",
+                delete_result
+                    .errors
+                    .iter()
+                    .fold(String::new(), |prev_string, cur_pair| {
+                        format!("{}[{}]", prev_string, cur_pair)
+                    })
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Return whether `policies` allow editing the DNS records of `zone_resource`. A deny policy covering the zone wins
+/// over the allow policies.
+fn allows_dns_write(policies: &[response::Policy], zone_resource: &str) -> bool {
+    let applies = |policy: &response::Policy| {
+        policy.permission_groups.iter().any(|group| {
+            group.id == DNS_WRITE_PERMISSION_GROUP || group.name.as_deref() == Some("DNS Write")
+        }) && covers_zone(&policy.resources, zone_resource)
+    };
+    let has = |effect: &str| {
+        policies
+            .iter()
+            .any(|policy| policy.effect == effect && applies(policy))
+    };
+    has("allow") && !has("deny")
+}
+
+/// Return whether the resources of a token policy include `zone_resource`, directly, through all zones, or through
+/// an account whose zones are all included.
+fn covers_zone(resources: &serde_json::Value, zone_resource: &str) -> bool {
+    let Some(resources) = resources.as_object() else {
+        return false;
+    };
+    resources.iter().any(|(name, nested)| {
+        name == zone_resource
+            || name == "com.cloudflare.api.account.zone.*"
+            || covers_zone(nested, zone_resource)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ZONE: &str = "com.cloudflare.api.account.zone.zone-id";
+
+    fn policy(effect: &str, resources: serde_json::Value) -> response::Policy {
+        serde_json::from_value(json!({
+            "effect": effect,
+            "permission_groups": [{ "id": DNS_WRITE_PERMISSION_GROUP, "name": "DNS Write" }],
+            "resources": resources,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn covers_exact_zone() {
+        assert!(covers_zone(&json!({ ZONE: "*" }), ZONE));
+        assert!(!covers_zone(
+            &json!({ "com.cloudflare.api.account.zone.other-id": "*" }),
+            ZONE
+        ));
+    }
+
+    #[test]
+    fn covers_all_zones() {
+        assert!(covers_zone(
+            &json!({ "com.cloudflare.api.account.zone.*": "*" }),
+            ZONE
+        ));
+    }
+
+    #[test]
+    fn covers_zones_of_account() {
+        assert!(covers_zone(
+            &json!({ "com.cloudflare.api.account.account-id": { "com.cloudflare.api.account.zone.*": "*" } }),
+            ZONE
+        ));
+        assert!(covers_zone(
+            &json!({ "com.cloudflare.api.account.account-id": { ZONE: "*" } }),
+            ZONE
+        ));
+        assert!(!covers_zone(
+            &json!({ "com.cloudflare.api.account.account-id": { "com.cloudflare.api.account.zone.other-id": "*" } }),
+            ZONE
+        ));
+    }
+
+    #[test]
+    fn deny_policy_overrides_allow() {
+        let allow = policy("allow", json!({ "com.cloudflare.api.account.zone.*": "*" }));
+        let deny = policy("deny", json!({ ZONE: "*" }));
+        let deny_other = policy(
+            "deny",
+            json!({ "com.cloudflare.api.account.zone.other-id": "*" }),
+        );
+
+        assert!(allows_dns_write(std::slice::from_ref(&allow), ZONE));
+        assert!(!allows_dns_write(&[allow, deny], ZONE));
+        let allow = policy("allow", json!({ ZONE: "*" }));
+        assert!(allows_dns_write(&[allow, deny_other], ZONE));
+        assert!(!allows_dns_write(
+            &[policy("deny", json!({ ZONE: "*" }))],
+            ZONE
+        ));
+    }
+
+    #[test]
+    fn other_permissions_do_not_allow() {
+        let policy: response::Policy = serde_json::from_value(json!({
+            "effect": "allow",
+            "permission_groups": [{ "id": "c8fed203ed3043cba015a93ad1616f1f", "name": "Zone Read" }],
+            "resources": { ZONE: "*" },
+        }))
+        .unwrap();
+        assert!(!allows_dns_write(&[policy], ZONE));
+    }
 }
@@ -2,6 +2,7 @@ pub mod cloudflare;
 
 use crate::RecordType;
 use core::net::IpAddr;
+use core::ops::Range;
 use serde::Deserialize;
 
 /// A enum representing all supported service provider and their build config. Should be deserialized from
@@ -13,16 +14,110 @@ pub enum ServiceProvider {
 }
 
 pub struct RecordDetail {
+    /// The id of the record at the service provider. Set on the records returned by `describe_records`, and needed
+    /// to update or delete a record.
+    pub id: Option<String>,
     pub subdomain_name: String,
     pub base_domain_name: String,
     pub ttl: u32,
     // This is currently only for cloudflare.
     pub proxied: Option<bool>,
-    pub ip_addr: IpAddr,
+    pub content: RecordContent,
+    /// The note attached to the record. `None` when there is none, or when it is not managed.
+    pub comment: Option<String>,
+    /// The tags attached to the record. `None` when they are not managed.
+    pub tags: Option<Vec<String>>,
+}
+
+/// The content of a record. The type of the record can be inferred from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordContent {
+    /// The address of an A or AAAA record.
+    Address(IpAddr),
+    Https(ServiceData),
+    Svcb(ServiceData),
+    /// The text of a TXT record.
+    Text(String),
+    /// The domain name a PTR record points to.
+    Pointer(String),
+}
+
+impl RecordContent {
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            RecordContent::Address(address) => (*address).into(),
+            RecordContent::Https(_) => RecordType::Https,
+            RecordContent::Svcb(_) => RecordType::Svcb,
+            RecordContent::Text(_) => RecordType::Txt,
+            RecordContent::Pointer(_) => RecordType::Ptr,
+        }
+    }
+}
+
+/// The data of an HTTPS or SVCB record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceData {
+    pub priority: u16,
+    pub target: String,
+    /// The SvcParams in presentation format, e.g. `alpn="h3,h2" ipv4hint="192.0.2.1"`.
+    pub params: String,
+}
+
+impl ServiceData {
+    /// Return whether the param `key` exists.
+    pub fn has_param(&self, key: &str) -> bool {
+        self.param_value_range(key).is_some()
+    }
+
+    /// Replace the value of the param `key` with `value` in place, keeping the other params and the quoting as they
+    /// are. A param that does not exist is appended.
+    pub fn set_param(&mut self, key: &str, value: &str) {
+        let Some(range) = self.param_value_range(key) else {
+            if !self.params.trim_end().is_empty() {
+                self.params.truncate(self.params.trim_end().len());
+                self.params.push(' ');
+            }
+            self.params.push_str(&format!("{}={}", key, value));
+            return;
+        };
+        let new_value = if self.params[range.clone()].starts_with('"') {
+            format!("\"{}\"", value)
+        } else {
+            value.to_string()
+        };
+        self.params.replace_range(range, &new_value);
+    }
+
+    /// Find where the value of the param `key` is in `params`. Params are separated by whitespace outside quotes.
+    fn param_value_range(&self, key: &str) -> Option<Range<usize>> {
+        let prefix = format!("{}=", key);
+        let mut token_start = None;
+        let mut in_quotes = false;
+        let mut escaped = false;
+        // A trailing space ends the last token in the loop.
+        for (index, character) in self.params.char_indices().chain([(self.params.len(), ' ')]) {
+            match character {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = !in_quotes,
+                _ if character.is_whitespace() && !in_quotes => {
+                    if let Some(start) = token_start.take() {
+                        if self.params[start..index].starts_with(&prefix) {
+                            return Some(start + prefix.len()..index);
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            token_start.get_or_insert(index);
+        }
+        None
+    }
 }
 
 // Impl this ourselves so that we can let the ddns changer consider there's no need to change when the api returned
-// record detail's proxied is None.
+// record detail's proxied is None. Tags are compared regardless of their order.
 impl PartialEq for RecordDetail {
     fn eq(&self, other: &Self) -> bool {
         let subdomain_name_eq = self.subdomain_name == other.subdomain_name;
@@ -30,19 +125,47 @@ impl PartialEq for RecordDetail {
         let ttl_eq = self.ttl == other.ttl;
         let proxied_eq =
             self.proxied.is_none() || other.proxied.is_none() || self.proxied == other.proxied;
-        let ip_addr_eq = self.ip_addr == other.ip_addr;
-        subdomain_name_eq && base_domain_name_eq && ttl_eq && proxied_eq && ip_addr_eq
+        let content_eq = self.content == other.content;
+        let comment_eq = self.comment == other.comment;
+        let tags_eq = match (&self.tags, &other.tags) {
+            (Some(tags), Some(other_tags)) => {
+                let mut tags = tags.clone();
+                let mut other_tags = other_tags.clone();
+                tags.sort();
+                tags.dedup();
+                other_tags.sort();
+                other_tags.dedup();
+                tags == other_tags
+            }
+            _ => true,
+        };
+        subdomain_name_eq
+            && base_domain_name_eq
+            && ttl_eq
+            && proxied_eq
+            && content_eq
+            && comment_eq
+            && tags_eq
+    }
+}
+
+/// Return the full domain name of `subdomain_name` under `base_domain_name`. Both `"@"` and `""` stand for the
+/// base domain itself.
+pub fn full_domain_name(subdomain_name: &str, base_domain_name: &str) -> String {
+    if subdomain_name == "@" || subdomain_name.is_empty() {
+        base_domain_name.to_string()
+    } else {
+        format!("{}.{}", subdomain_name, base_domain_name)
     }
 }
 
 /// A trait for the api interface used in ddns changer.
 ///
 /// Now, it is garanteed that whenever an instance of `ApiInterface`
-/// is created, `authorize` is first called. Then, for every distinct
-/// domain name (subdomain_name + domain_name), `describe_record`
-/// is called before either `create_record` or `update_record` is called,
-/// so if `create_record` or `update_record` needs something like "record_id" or other api-specific information,
-/// these infomation can be stored in the instance when `describe_record` is called.
+/// is created, `authorize` is first called. A name may hold a set of
+/// records of the same type, e.g. the addresses of a multi-homed host,
+/// so every record returned by `describe_records` carries its `id`, which
+/// is passed back to `update_record` and `delete_record`.
 pub trait ApiInterface {
     /// The Config to build the ApiInterface from. This should be the struct in the config file.
     type BuildConfigType: Clone + for<'a> Deserialize<'a>;
@@ -50,34 +173,120 @@ pub trait ApiInterface {
     /// Returns a new interface instance.
     fn new(config: Self::BuildConfigType) -> Self;
 
-    /// Authorize self to the api. If failed, return a string specify the reason.
-    fn authorize(&mut self) -> Result<(), String>;
+    /// Authorize self to the api for managing the records of `base_domain_name`. If failed, return a string
+    /// specify the reason.
+    fn authorize(&mut self, base_domain_name: &str) -> Result<(), String>;
+
+    /// Return the details of all records of `record_type` of a domain from api, with their `id` set. If the api
+    /// didn't success, return a String containing fail reason.
+    fn describe_records(
+        &mut self,
+        subdomain_name: &str,
+        base_domain_name: &str,
+        record_type: RecordType,
+    ) -> Result<Vec<RecordDetail>, String>;
 
     /// Return the detail of a record from api. If the record doesn't exist, return
     /// Ok(None). If the api didn't success, return a String containing fail reason.
     ///
     /// Note: having multiple record for one domain is also considered an error.
-    ///
-    /// It is the interface's responsibility to store additional infomation that may be needed
-    /// for create or update the record.
     fn describe_record(
         &mut self,
         subdomain_name: &str,
         base_domain_name: &str,
         record_type: RecordType,
-    ) -> Result<Option<RecordDetail>, String>;
+    ) -> Result<Option<RecordDetail>, String> {
+        let mut records = self.describe_records(subdomain_name, base_domain_name, record_type)?;
+        match records.len() {
+            0 | 1 => Ok(records.pop()),
+            _ => Err(format!(
+                "More than 1 records exists for {} record of {}.",
+                record_type,
+                full_domain_name(subdomain_name, base_domain_name)
+            )),
+        }
+    }
 
-    /// Create a record of `subdomain_name`.`base_domain_name` with `record_content`. Only called when
-    /// `describe_record` returned `Some(None)`, and user sets to create on none-exsistent domains.
-    /// The type of the record can be inferred from `IpAddr` struct.
+    /// Create a record of `subdomain_name`.`base_domain_name` with `record_content`. Called when the record
+    /// described does not exist or a set needs one more record, and user sets to create on none-exsistent
+    /// domains. The type of the record can be inferred from `RecordContent`. `id` is ignored.
     ///
     /// On success, return `Ok()`; on failure, return `Err` containing reason string.
     fn create_record(&self, record_detail: RecordDetail) -> Result<(), String>;
 
-    /// Change the content of `subdomain_name`.`base_domain_name` to `record_content`. Only called when
-    /// `describe_record` reports that exactely one domain exists. The type of the record can be inferred
-    /// from `IpAddr` struct.
+    /// Change the record of `id` to `record_content`. `id` is taken from a record returned by
+    /// `describe_records`. The type of the record can be inferred from `RecordContent`.
     ///
     /// On success, return `Ok()`; on failure, return `Err` containing reason string.
     fn update_record(&self, record_detail: RecordDetail) -> Result<(), String>;
+
+    /// Delete the record of `id`, a record returned by `describe_records`.
+    ///
+    /// On success, return `Ok()`; on failure, return `Err` containing reason string.
+    fn delete_record(&self, record_detail: &RecordDetail) -> Result<(), String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(params: &str) -> ServiceData {
+        ServiceData {
+            priority: 1,
+            target: ".".to_string(),
+            params: params.to_string(),
+        }
+    }
+
+    #[test]
+    fn set_param_keeps_quoted_value_with_spaces() {
+        let mut data = data("alpn=\"h3, h2\" ipv4hint=\"192.0.2.1\" port=443");
+        data.set_param("ipv4hint", "198.51.100.7");
+        assert_eq!(
+            data.params,
+            "alpn=\"h3, h2\" ipv4hint=\"198.51.100.7\" port=443"
+        );
+        data.set_param("alpn", "h2");
+        assert_eq!(
+            data.params,
+            "alpn=\"h2\" ipv4hint=\"198.51.100.7\" port=443"
+        );
+    }
+
+    #[test]
+    fn set_param_skips_escaped_quote() {
+        let mut data = data("key65000=\"a\\\" ipv4hint=x\" ipv4hint=192.0.2.1");
+        data.set_param("ipv4hint", "198.51.100.7");
+        assert_eq!(
+            data.params,
+            "key65000=\"a\\\" ipv4hint=x\" ipv4hint=198.51.100.7"
+        );
+    }
+
+    #[test]
+    fn set_param_matches_whole_key() {
+        let mut data = data("ipv4hints=192.0.2.9 ipv4hint=192.0.2.1");
+        data.set_param("ipv4hint", "198.51.100.7");
+        assert_eq!(data.params, "ipv4hints=192.0.2.9 ipv4hint=198.51.100.7");
+        assert!(!data.has_param("ipv4"));
+    }
+
+    #[test]
+    fn set_param_replaces_last_param() {
+        let mut data = data("alpn=h3 ipv6hint=2001:db8::1");
+        data.set_param("ipv6hint", "2001:db8::7");
+        assert_eq!(data.params, "alpn=h3 ipv6hint=2001:db8::7");
+    }
+
+    #[test]
+    fn set_param_appends_missing_param() {
+        let mut appended = data("alpn=h3 ");
+        assert!(!appended.has_param("ipv4hint"));
+        appended.set_param("ipv4hint", "198.51.100.7");
+        assert_eq!(appended.params, "alpn=h3 ipv4hint=198.51.100.7");
+
+        let mut empty = data("");
+        empty.set_param("ipv4hint", "198.51.100.7");
+        assert_eq!(empty.params, "ipv4hint=198.51.100.7");
+    }
 }
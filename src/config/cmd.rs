@@ -52,4 +52,9 @@ pub struct CmdArgs {
         default_value_t = 4
     )]
     pub thread_number: u8,
+    #[arg(
+        long,
+        help = "Keep the state needed across runs in this json file, e.g. for on_address_loss. Will create all parent folder if not exist."
+    )]
+    pub state_file: Option<String>,
 }
@@ -1,5 +1,20 @@
-use crate::{api::ServiceProvider, RecordType};
+use crate::{
+    address_filter::AddressFilter,
+    address_mapping::MappingRule,
+    api::ServiceProvider,
+    config::RecordTypes,
+    ip_source::{
+        extract::ExtractRule,
+        fallback::FallbackSourceConfig,
+        url::{default_timeout, UrlSourceConfig},
+        AddressFamily, IpSource,
+    },
+    template::Template,
+    RecordType,
+};
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
 
 pub enum FileType {
     Json,
@@ -8,40 +23,258 @@ pub enum FileType {
 
 #[derive(Debug, Deserialize)]
 pub struct DDNSSetings {
-    pub get_ip_urls: GetIPUrls,
+    /// Kept for compatibility. Equivalent to url sources named `ipv4` and `ipv6` in `ip_sources`.
+    pub get_ip_urls: Option<GetIPUrls>,
+    /// Named ip sources that domains and subdomains choose from.
+    #[serde(default)]
+    pub ip_sources: HashMap<String, IpSource>,
     pub domain_settings: Vec<SingleDomainSettings>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GetIPUrls {
-    pub ipv4: String,
-    pub ipv6: String,
+    pub ipv4: OneOrMany<String>,
+    pub ipv6: OneOrMany<String>,
+    /// Seconds to wait for every url.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// When set, all urls are queried and an address is only trusted when this many urls agree on it.
+    pub required_agreement: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Allows a field to be written as either a single value or an array of values.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Self {
+        match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+/// The name of the ip source used by A records when a domain does not choose one.
+pub const DEFAULT_IPV4_SOURCE: &str = "ipv4";
+/// The name of the ip source used by AAAA records when a domain does not choose one.
+pub const DEFAULT_IPV6_SOURCE: &str = "ipv6";
+
+impl GetIPUrls {
+    /// Convert into the sources named [`DEFAULT_IPV4_SOURCE`] and [`DEFAULT_IPV6_SOURCE`].
+    pub fn into_ip_sources(self) -> HashMap<String, IpSource> {
+        let timeout = self.timeout;
+        let required_agreement = self.required_agreement;
+        let to_source = |urls: OneOrMany<String>| {
+            let mut sources: Vec<IpSource> = Vec::<String>::from(urls)
+                .into_iter()
+                .map(|url| {
+                    IpSource::Url(UrlSourceConfig {
+                        url,
+                        timeout,
+                        extract: ExtractRule::Plain,
+                    })
+                })
+                .collect();
+            if sources.len() == 1 && required_agreement.is_none() {
+                sources.remove(0)
+            } else {
+                IpSource::Fallback(FallbackSourceConfig {
+                    sources,
+                    required_agreement,
+                })
+            }
+        };
+        HashMap::from([
+            (DEFAULT_IPV4_SOURCE.to_string(), to_source(self.ipv4)),
+            (DEFAULT_IPV6_SOURCE.to_string(), to_source(self.ipv6)),
+        ])
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct SingleDomainSettings {
     pub enabled: bool,
     pub domain_name: String,
     pub service_provider: ServiceProvider,
-    pub record_type: RecordType,
+    /// The record types managed for every subdomain, one changer is run for each of them.
+    pub record_type: RecordTypes,
+    /// The name of the ip source in `ip_sources` used by the subdomains that do not choose one.
+    pub ip_source: Option<String>,
     #[serde(default = "default_create_new_record")]
     pub create_new_record: bool,
     pub subdomains: Vec<SubDomainSettings>,
+    #[serde(flatten)]
+    pub address_filter: AddressFilter,
+    /// Rules rewriting the acquired address of every subdomain, tried after the ones of the subdomain.
+    #[serde(default)]
+    pub mappings: Vec<MappingRule>,
+    /// What to do with the records whose address cannot be acquired for several runs in a row.
+    pub on_address_loss: Option<AddressLossPolicy>,
+    /// The TXT record published next to every subdomain when `record_type` includes TXT.
+    pub txt_record: Option<TxtRecordSettings>,
+    /// The reverse zones whose PTR records follow the A and AAAA records of the subdomains.
+    #[serde(default)]
+    pub reverse_zones: Vec<ReverseZoneSettings>,
+}
+
+impl SingleDomainSettings {
+    /// The name of the ip source used for `family` by the subdomains that do not choose one.
+    pub fn ip_source_name(&self, family: AddressFamily) -> &str {
+        match (&self.ip_source, family) {
+            (Some(name), _) => name,
+            (None, AddressFamily::Ipv4) => DEFAULT_IPV4_SOURCE,
+            (None, AddressFamily::Ipv6) => DEFAULT_IPV6_SOURCE,
+        }
+    }
+
+    /// The address families needed by the records of `record_type`. A TXT record only needs the ones its template
+    /// uses.
+    pub fn address_families(&self, record_type: RecordType) -> Vec<AddressFamily> {
+        match (record_type, &self.txt_record) {
+            (RecordType::Txt, Some(txt_record)) => txt_record.content.address_families(),
+            (RecordType::Txt, None) => Vec::new(),
+            _ => record_type.address_families().to_vec(),
+        }
+    }
 }
 
 fn default_create_new_record() -> bool {
     true
 }
 
-#[derive(Debug, Deserialize)]
+/// Removes or parks the record of a subdomain once its address could not be acquired for `after_runs` consecutive
+/// runs, so that clients do not hang on an address that is gone.
+#[derive(Debug, Deserialize, Clone)]
+pub struct AddressLossPolicy {
+    #[serde(flatten)]
+    pub action: AddressLossAction,
+    #[serde(default = "default_after_runs")]
+    pub after_runs: u32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum AddressLossAction {
+    /// Delete the record.
+    Delete,
+    /// Point the record at a fixed address of the same family instead, e.g. a server showing a maintenance page.
+    Park { address: OneOrMany<IpAddr> },
+}
+
+impl AddressLossAction {
+    /// The address a record of `record_type` is parked at, if any.
+    pub fn park_address(&self, record_type: RecordType) -> Option<IpAddr> {
+        match self {
+            AddressLossAction::Delete => None,
+            AddressLossAction::Park { address } => Vec::from(address.clone())
+                .into_iter()
+                .find(|address| RecordType::from(*address) == record_type),
+        }
+    }
+}
+
+fn default_after_runs() -> u32 {
+    3
+}
+
+/// A TXT record carrying the current addresses of a subdomain, for monitoring and humans to see when they changed.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TxtRecordSettings {
+    /// The label put before the name of the subdomain to get the name of the TXT record.
+    #[serde(default = "default_txt_prefix")]
+    pub prefix: String,
+    pub content: Template,
+}
+
+impl TxtRecordSettings {
+    /// The name of the TXT record of the subdomain `subdomain_name`, relative to the domain.
+    pub fn record_name(&self, subdomain_name: &str) -> String {
+        if subdomain_name == "@" || subdomain_name.is_empty() {
+            self.prefix.clone()
+        } else {
+            format!("{}.{}", self.prefix, subdomain_name)
+        }
+    }
+}
+
+fn default_txt_prefix() -> String {
+    "_ddns".to_string()
+}
+
+/// A delegated `in-addr.arpa` or `ip6.arpa` zone, e.g. `8.b.d.0.1.0.0.2.ip6.arpa`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReverseZoneSettings {
+    pub name: String,
+    pub service_provider: ServiceProvider,
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct SubDomainSettings {
     pub name: String,
     #[serde(default = "default_ttl")]
     pub ttl: u32,
     pub proxied: Option<bool>,
     pub interface_id: Option<String>,
+    /// The MAC address of a device using SLAAC without privacy extensions. Its EUI-64 interface id is used as
+    /// `interface_id`.
+    pub mac_address: Option<String>,
+    /// The number of leading bits of the acquired IPv6 address kept in the record, i.e. the length of the prefix
+    /// delegated by the ISP.
+    #[serde(default = "default_prefix_length")]
+    pub prefix_length: u8,
+    /// Hex of the bits between `prefix_length` and `subnet_prefix_length`.
+    pub subnet_id: Option<String>,
+    /// The length of the prefix of the subnet the host is in. `interface_id` fills the bits after it.
+    #[serde(default = "default_prefix_length")]
+    pub subnet_prefix_length: u8,
+    /// The name of the ip source in `ip_sources` used instead of the one of the domain.
+    pub ip_source: Option<String>,
+    /// The names of several ip sources whose addresses are all published as a set of A or AAAA records, e.g. for a
+    /// multi-homed host. Used instead of `ip_source` for those records.
+    #[serde(default)]
+    pub ip_sources: Vec<String>,
+    /// A fixed address written into the record instead of the acquired one.
+    pub address: Option<IpAddr>,
+    /// Rules rewriting the acquired address, tried before the ones of the domain.
+    #[serde(default)]
+    pub mappings: Vec<MappingRule>,
+    /// The record types of the domain this subdomain takes part in. Used to opt out of one of them.
+    pub record_type: Option<RecordTypes>,
+    /// The comment of every record written for this subdomain, e.g. to tell the records managed by the script.
+    pub comment: Option<Template>,
+    /// The tags of every record written for this subdomain.
+    pub tags: Option<Vec<String>>,
+}
+
+impl SubDomainSettings {
+    /// Whether a record of `record_type` is managed for this subdomain. Without `record_type`, a subdomain with a
+    /// static address only takes part in the record types carrying the family of the address.
+    pub fn manages(&self, record_type: RecordType) -> bool {
+        match (&self.record_type, self.address) {
+            (Some(record_types), _) => record_types.contains(record_type),
+            (None, Some(address)) => record_type
+                .address_families()
+                .contains(&AddressFamily::from(address)),
+            (None, None) => true,
+        }
+    }
+
+    /// Whether the records of `record_type` of this subdomain are a set with an address from every source in
+    /// `ip_sources`.
+    pub fn publishes_set(&self, record_type: RecordType) -> bool {
+        !self.ip_sources.is_empty() && matches!(record_type, RecordType::A | RecordType::Aaaa)
+    }
 }
 
 fn default_ttl() -> u32 {
     1
 }
+
+fn default_prefix_length() -> u8 {
+    64
+}
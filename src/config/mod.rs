@@ -3,13 +3,42 @@ pub mod file;
 
 use std::net::IpAddr;
 
-use serde::{Deserialize, Serialize};
+use serde::de::{self, IntoDeserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+use crate::ip_source::AddressFamily;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RecordType {
     A,
     #[serde(rename = "AAAA")]
     Aaaa,
+    /// Only the `ipv4hint` and `ipv6hint` params of existing records are managed.
+    #[serde(rename = "HTTPS")]
+    Https,
+    /// Only the `ipv4hint` and `ipv6hint` params of existing records are managed.
+    #[serde(rename = "SVCB")]
+    Svcb,
+    /// A record next to the one of the subdomain, whose content is rendered from a template.
+    #[serde(rename = "TXT")]
+    Txt,
+    /// Follows the A and AAAA records of the domain in its reverse zones, so it cannot be chosen on its own.
+    #[serde(rename = "PTR")]
+    Ptr,
+}
+
+impl RecordType {
+    /// The address families whose addresses can be written into a record of this type.
+    pub fn address_families(&self) -> &'static [AddressFamily] {
+        match self {
+            RecordType::A => &[AddressFamily::Ipv4],
+            RecordType::Aaaa => &[AddressFamily::Ipv6],
+            RecordType::Https | RecordType::Svcb | RecordType::Txt => {
+                &[AddressFamily::Ipv4, AddressFamily::Ipv6]
+            }
+            RecordType::Ptr => &[],
+        }
+    }
 }
 
 impl std::fmt::Display for RecordType {
@@ -17,6 +46,10 @@ impl std::fmt::Display for RecordType {
         match self {
             RecordType::A => write!(f, "A"),
             RecordType::Aaaa => write!(f, "AAAA"),
+            RecordType::Https => write!(f, "HTTPS"),
+            RecordType::Svcb => write!(f, "SVCB"),
+            RecordType::Txt => write!(f, "TXT"),
+            RecordType::Ptr => write!(f, "PTR"),
         }
     }
 }
@@ -29,3 +62,147 @@ impl From<IpAddr> for RecordType {
         }
     }
 }
+
+/// The record types managed by a domain or a subdomain. Written in the config file as a record type, an array of
+/// record types, or `"both"` for A and AAAA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordTypes(Vec<RecordType>);
+
+impl<'de> Deserialize<'de> for RecordTypes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let listed = deserializer.deserialize_any(RecordTypesVisitor)?;
+        RecordTypes::try_from(listed).map_err(de::Error::custom)
+    }
+}
+
+struct RecordTypesVisitor;
+
+impl<'de> Visitor<'de> for RecordTypesVisitor {
+    type Value = Vec<RecordType>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "a record type (\"A\", \"AAAA\", \"HTTPS\", \"SVCB\" or \"TXT\"), \"both\" for A and AAAA, or an array of record types"
+        )
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        if value == "both" {
+            return Ok(vec![RecordType::A, RecordType::Aaaa]);
+        }
+        let record_type = RecordType::deserialize(value.into_deserializer())
+            .map_err(|_: E| E::invalid_value(de::Unexpected::Str(value), &self))?;
+        Ok(vec![record_type])
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+        let mut record_types = Vec::new();
+        while let Some(record_type) = seq.next_element()? {
+            record_types.push(record_type);
+        }
+        Ok(record_types)
+    }
+}
+
+impl TryFrom<Vec<RecordType>> for RecordTypes {
+    type Error = String;
+
+    fn try_from(listed: Vec<RecordType>) -> Result<Self, Self::Error> {
+        if listed.is_empty() {
+            return Err("At least one record type is needed".to_string());
+        }
+        if listed.contains(&RecordType::Ptr) {
+            return Err("PTR records are managed through reverse_zones".to_string());
+        }
+        // Drop duplicates and keep a stable order.
+        Ok(RecordTypes(
+            [
+                RecordType::A,
+                RecordType::Aaaa,
+                RecordType::Https,
+                RecordType::Svcb,
+                RecordType::Txt,
+            ]
+            .into_iter()
+            .filter(|record_type| listed.contains(record_type))
+            .collect(),
+        ))
+    }
+}
+
+impl RecordTypes {
+    pub fn iter(&self) -> impl Iterator<Item = RecordType> + '_ {
+        self.0.iter().copied()
+    }
+
+    pub fn contains(&self, record_type: RecordType) -> bool {
+        self.0.contains(&record_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_types(value: &str) -> Result<Vec<RecordType>, String> {
+        match serde_json::from_str::<RecordTypes>(value) {
+            Ok(record_types) => Ok(record_types.iter().collect()),
+            Err(reason) => Err(reason.to_string()),
+        }
+    }
+
+    #[test]
+    fn single_record_type() {
+        assert_eq!(record_types("\"AAAA\""), Ok(vec![RecordType::Aaaa]));
+        assert_eq!(record_types("\"HTTPS\""), Ok(vec![RecordType::Https]));
+    }
+
+    #[test]
+    fn both_is_a_and_aaaa() {
+        assert_eq!(
+            record_types("\"both\""),
+            Ok(vec![RecordType::A, RecordType::Aaaa])
+        );
+    }
+
+    #[test]
+    fn list_without_duplicates_in_stable_order() {
+        assert_eq!(
+            record_types("[\"TXT\", \"AAAA\", \"A\", \"AAAA\"]"),
+            Ok(vec![RecordType::A, RecordType::Aaaa, RecordType::Txt])
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_record_types() {
+        let error = record_types("\"MX\"").unwrap_err();
+        assert!(
+            error.starts_with("invalid value: string \"MX\", expected a record type (\"A\", \"AAAA\", \"HTTPS\", \"SVCB\" or \"TXT\"), \"both\" for A and AAAA, or an array of record types"),
+            "{}",
+            error
+        );
+        let error = record_types("5").unwrap_err();
+        assert!(
+            error.starts_with("invalid type: integer `5`, expected a record type"),
+            "{}",
+            error
+        );
+        assert!(record_types("[\"A\", \"MX\"]")
+            .unwrap_err()
+            .starts_with("unknown variant `MX`"));
+    }
+
+    #[test]
+    fn rejects_ptr_and_empty_list() {
+        assert!(record_types("\"PTR\"")
+            .unwrap_err()
+            .starts_with("PTR records are managed through reverse_zones"));
+        assert!(record_types("[\"A\", \"PTR\"]")
+            .unwrap_err()
+            .starts_with("PTR records are managed through reverse_zones"));
+        assert!(record_types("[]")
+            .unwrap_err()
+            .starts_with("At least one record type is needed"));
+    }
+}
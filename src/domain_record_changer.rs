@@ -1,13 +1,42 @@
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
 
 use crate::{
-    api::{ApiInterface, RecordDetail},
+    address,
+    address_filter::AddressFilter,
+    address_mapping::{self, MappingRule},
+    api::{ApiInterface, RecordContent, RecordDetail},
     config::{
-        file::{SingleDomainSettings, SubDomainSettings},
+        file::{
+            AddressLossAction, AddressLossPolicy, SingleDomainSettings, SubDomainSettings,
+            TxtRecordSettings,
+        },
         RecordType,
     },
+    ip_source::AddressFamily,
+    state::{lock, State},
+    template::TemplateValues,
 };
 
+/// Why the address of a subdomain is not available.
+enum NoAddress {
+    /// The ip source of the name did not acquire an address.
+    Lost(String),
+    /// Anything else. The reason is already logged.
+    Failed,
+}
+
+/// A reverse zone whose PTR records follow the A and AAAA records of the domain. `name` is in lowercase without
+/// the trailing dot.
+pub struct ReverseZone<S>
+where
+    S: ApiInterface,
+{
+    pub name: String,
+    pub api_interface: S,
+}
+
 pub struct DomainRecordChanger<S>
 where
     S: ApiInterface,
@@ -17,27 +46,56 @@ where
     record_type: RecordType,
     create_new_record: bool,
     subdomains: Vec<SubDomainSettings>,
-    ip_address: IpAddr,
+    /// The name of the ip source of every address family used by the subdomains that do not choose one.
+    ip_source: HashMap<AddressFamily, String>,
+    /// The addresses acquired from the ip sources, by the name of the source and the address family.
+    addresses: HashMap<(String, AddressFamily), IpAddr>,
+    address_filter: AddressFilter,
+    mappings: Vec<MappingRule>,
+    on_address_loss: Option<AddressLossPolicy>,
+    txt_record: Option<TxtRecordSettings>,
     api_interface: S,
+    reverse_zones: Vec<ReverseZone<S>>,
+    /// The subdomain keeping the PTR record of every address published in this run, i.e. the first one publishing
+    /// it, so that subdomains sharing an address do not take turns rewriting it.
+    ptr_owners: HashMap<IpAddr, String>,
 }
 
 impl<S> DomainRecordChanger<S>
 where
     S: ApiInterface,
 {
-    pub fn new(settings: SingleDomainSettings, ip_address: IpAddr, api_interface: S) -> Self {
+    pub fn new(
+        settings: SingleDomainSettings,
+        record_type: RecordType,
+        addresses: HashMap<(String, AddressFamily), IpAddr>,
+        api_interface: S,
+        reverse_zones: Vec<ReverseZone<S>>,
+    ) -> Self {
         DomainRecordChanger {
+            ip_source: [AddressFamily::Ipv4, AddressFamily::Ipv6]
+                .into_iter()
+                .map(|family| (family, settings.ip_source_name(family).to_string()))
+                .collect(),
             enabled: settings.enabled,
             domain_name: settings.domain_name,
-            record_type: settings.record_type,
+            record_type,
             create_new_record: settings.create_new_record,
             subdomains: settings.subdomains,
-            ip_address,
+            addresses,
+            address_filter: settings.address_filter,
+            mappings: settings.mappings,
+            on_address_loss: settings.on_address_loss,
+            txt_record: settings.txt_record,
             api_interface,
+            reverse_zones,
+            ptr_owners: HashMap::new(),
         }
     }
 
-    pub fn start_ddns(&mut self) {
+    /// Update the records of all subdomains. `state` keeps what is needed across runs and is shared by all
+    /// changers.
+    pub fn start_ddns(&mut self, state: &Mutex<State>) {
         log::debug!(
             "DDNS for {} of record type {} started",
             self.domain_name,
@@ -54,15 +112,33 @@ where
         }
 
         log::debug!("Authorizing api");
-        match self.api_interface.authorize() {
+        match self.api_interface.authorize(&self.domain_name) {
             Ok(_) => log::debug!("Authorize api succeeded."),
             Err(message) => {
                 log::error!("Authorize api failed: {}", message);
                 return;
             }
         }
+        self.reverse_zones
+            .retain_mut(|zone| match zone.api_interface.authorize(&zone.name) {
+                Ok(_) => true,
+                Err(message) => {
+                    log::error!(
+                        "Authorize api of reverse zone {} failed: {}",
+                        zone.name,
+                        message
+                    );
+                    false
+                }
+            });
 
-        'subdomain_iter: for subdomain_settings in &self.subdomains {
+        // Taken out of self while iterating, so that the helper methods can still borrow self mutably.
+        let subdomains = std::mem::take(&mut self.subdomains);
+        let record_type = self.record_type;
+        'subdomain_iter: for subdomain_settings in subdomains
+            .iter()
+            .filter(|subdomain_settings| subdomain_settings.manages(record_type))
+        {
             let full_domain_name =
                 if subdomain_settings.name == "@" || subdomain_settings.name.is_empty() {
                     self.domain_name.clone()
@@ -71,52 +147,50 @@ where
                 };
             log::debug!("Start DDNS for {}", full_domain_name);
 
-            // Convert the ip address to ip to be sent by api request.
-            let content_ip: IpAddr = match self.ip_address {
-                IpAddr::V4(v4_address) => v4_address.into(),
-                IpAddr::V6(mut v6_address) => {
-                    if let Some(custom_interface_id) = &subdomain_settings.interface_id {
-                        log::debug!(
-                            "The custom interface id for {} exists: {}",
-                            full_domain_name,
-                            custom_interface_id
-                        );
-                        let mut v6_address_arr = v6_address.segments();
-
-                        let interface_addr = custom_interface_id.parse::<Ipv6Addr>();
-                        if interface_addr.is_err() {
-                            log::error!("The custom interface id for {} cannot be parsed into a valid ipv6 addr. Settings string: {}.", full_domain_name, custom_interface_id);
-                            continue 'subdomain_iter;
-                        }
-                        // Now interface_addr is definitely Ok()
-                        let interface_addr = interface_addr.unwrap();
-                        let interface_addr_arr = interface_addr.segments();
-
-                        // Check if the first 64 bit is 0.
-                        if interface_addr_arr.iter().take(4).sum::<u16>() != 0 {
-                            log::warn!(
-                                "The first 64 bits of the interface id are not 0. They are ignored."
+            let content_ip = match self.record_type {
+                RecordType::A | RecordType::Aaaa
+                    if subdomain_settings.publishes_set(record_type) =>
+                {
+                    self.update_record_set(state, subdomain_settings, &full_domain_name);
+                    continue 'subdomain_iter;
+                }
+                RecordType::A | RecordType::Aaaa => {
+                    let family = self.record_type.address_families()[0];
+                    match self.subdomain_address(subdomain_settings, &full_domain_name, family) {
+                        Ok(address) => address,
+                        Err(NoAddress::Lost(ip_source)) => {
+                            log::error!(
+                                "Skipping {} record update for {} as a result of previously failed ip address aquisition from {}.",
+                                self.record_type,
+                                full_domain_name,
+                                ip_source
                             );
+                            self.handle_address_loss(state, subdomain_settings, &full_domain_name);
+                            continue 'subdomain_iter;
                         }
-
-                        // Copy the last 8 bytes into v6_address_arr and compose a new v6 address.
-                        v6_address_arr[4..8].copy_from_slice(&interface_addr_arr[4..8]);
-                        v6_address = Ipv6Addr::from(v6_address_arr);
-                        log::debug!(
-                            "The ip for {} will be {}, different from current machine's ip.",
-                            full_domain_name,
-                            v6_address.to_string()
-                        );
+                        Err(NoAddress::Failed) => continue 'subdomain_iter,
                     }
-                    v6_address.into()
                 }
+                RecordType::Https | RecordType::Svcb => {
+                    self.update_service_hints(subdomain_settings, &full_domain_name);
+                    continue 'subdomain_iter;
+                }
+                RecordType::Txt => {
+                    self.update_txt_record(subdomain_settings, &full_domain_name);
+                    continue 'subdomain_iter;
+                }
+                RecordType::Ptr => unreachable!("PTR records cannot be chosen in record_type"),
             };
+            if self.on_address_loss.is_some() {
+                lock(state).clear_missing_address_runs(self.record_type, &full_domain_name);
+            }
+            self.claim_ptr(content_ip, &full_domain_name);
 
             // Get the information
             let record_detail = match self.api_interface.describe_record(
                 &subdomain_settings.name,
                 &self.domain_name,
-                self.ip_address.into(),
+                self.record_type,
             ) {
                 Ok(result) => result,
                 Err(message) => {
@@ -131,12 +205,24 @@ where
             };
 
             // The record should be like this
+            let mut values = TemplateValues::new(&full_domain_name);
+            values.set_address(content_ip);
+            let content = RecordContent::Address(content_ip);
+            let (comment, tags) = record_labels(
+                subdomain_settings,
+                &values,
+                &content,
+                record_detail.as_ref(),
+            );
             let target_record = RecordDetail {
+                id: record_detail.as_ref().and_then(|record| record.id.clone()),
                 subdomain_name: subdomain_settings.name.clone(),
                 base_domain_name: self.domain_name.clone(),
                 ttl: subdomain_settings.ttl,
                 proxied: subdomain_settings.proxied,
-                ip_addr: content_ip,
+                content,
+                comment,
+                tags,
             };
 
             // If no record presents, create one (or not).
@@ -148,11 +234,20 @@ where
                         full_domain_name
                     );
                     match self.api_interface.create_record(target_record) {
-                        Ok(_) => log::info!(
-                            "Created new {} record of {}.",
-                            self.record_type,
-                            full_domain_name
-                        ),
+                        Ok(_) => {
+                            log::info!(
+                                "Created new {} record of {}.",
+                                self.record_type,
+                                full_domain_name
+                            );
+                            self.sync_ptr(
+                                state,
+                                subdomain_settings,
+                                &full_domain_name,
+                                content_ip,
+                                None,
+                            );
+                        }
                         Err(message) => {
                             log::error!(
                                 "Create new {} record of {} failed: {}",
@@ -179,9 +274,23 @@ where
                     self.record_type,
                     full_domain_name
                 );
+                // Also fills in PTR records missing since before the reverse zone was configured.
+                if !lock(state).ptr_synced(content_ip, &full_domain_name) {
+                    self.sync_ptr(
+                        state,
+                        subdomain_settings,
+                        &full_domain_name,
+                        content_ip,
+                        None,
+                    );
+                }
                 continue 'subdomain_iter;
             }
 
+            let old_address = match record_detail.content {
+                RecordContent::Address(address) => Some(address),
+                _ => None,
+            };
             match self.api_interface.update_record(target_record) {
                 Ok(_) => {
                     log::info!(
@@ -189,6 +298,13 @@ where
                         self.record_type,
                         full_domain_name
                     );
+                    self.sync_ptr(
+                        state,
+                        subdomain_settings,
+                        &full_domain_name,
+                        content_ip,
+                        old_address,
+                    );
                 }
                 Err(message) => {
                     log::error!(
@@ -201,5 +317,1041 @@ where
                 }
             };
         }
+        self.subdomains = subdomains;
+    }
+
+    /// Compute the address of `family` written for a subdomain, from its static address or the acquired one. If
+    /// it cannot be computed, the reason is logged unless the address is lost.
+    fn subdomain_address(
+        &self,
+        subdomain_settings: &SubDomainSettings,
+        full_domain_name: &str,
+        family: AddressFamily,
+    ) -> Result<IpAddr, NoAddress> {
+        let ip_source = subdomain_settings
+            .ip_source
+            .as_deref()
+            .unwrap_or(&self.ip_source[&family]);
+        self.source_address(subdomain_settings, full_domain_name, family, ip_source)
+    }
+
+    /// Compute the address of `family` written for a subdomain from the address acquired from `ip_source`, or its
+    /// static address. Errors are the same as [`Self::subdomain_address`].
+    fn source_address(
+        &self,
+        subdomain_settings: &SubDomainSettings,
+        full_domain_name: &str,
+        family: AddressFamily,
+        ip_source: &str,
+    ) -> Result<IpAddr, NoAddress> {
+        let ip_address = match (
+            subdomain_settings.address,
+            self.addresses.get(&(ip_source.to_string(), family)),
+        ) {
+            (Some(static_address), _) => {
+                if AddressFamily::from(static_address) != family {
+                    log::error!(
+                        "The static address {} of {} cannot be written into a {} record.",
+                        static_address,
+                        full_domain_name,
+                        self.record_type
+                    );
+                    return Err(NoAddress::Failed);
+                }
+                if subdomain_settings.interface_id.is_some()
+                    || subdomain_settings.mac_address.is_some()
+                {
+                    log::warn!(
+                        "{} has a static address, its interface id is ignored.",
+                        full_domain_name
+                    );
+                }
+                static_address
+            }
+            (None, Some(address)) => {
+                match address_mapping::map_address(
+                    subdomain_settings.mappings.iter().chain(&self.mappings),
+                    *address,
+                ) {
+                    Ok(mapped) => {
+                        if mapped != *address {
+                            log::debug!(
+                                "The acquired ip {} for {} is mapped to {}.",
+                                address,
+                                full_domain_name,
+                                mapped
+                            );
+                        }
+                        mapped
+                    }
+                    Err(reason) => {
+                        log::error!("Mapping the ip for {} failed: {}", full_domain_name, reason);
+                        return Err(NoAddress::Failed);
+                    }
+                }
+            }
+            (None, None) => return Err(NoAddress::Lost(ip_source.to_string())),
+        };
+        // Convert the ip address to ip to be sent by api request.
+        let content_ip: IpAddr = match ip_address {
+            IpAddr::V4(v4_address) => v4_address.into(),
+            // Static addresses are used as is.
+            IpAddr::V6(v6_address) if subdomain_settings.address.is_some() => v6_address.into(),
+            IpAddr::V6(v6_address) => {
+                let interface_id = match (
+                    &subdomain_settings.interface_id,
+                    &subdomain_settings.mac_address,
+                ) {
+                    (Some(_), Some(_)) => {
+                        log::error!(
+                            "{} has both interface_id and mac_address. Only one of them can be used.",
+                            full_domain_name
+                        );
+                        return Err(NoAddress::Failed);
+                    }
+                    (Some(custom_interface_id), None) => {
+                        log::debug!(
+                            "The custom interface id for {} exists: {}",
+                            full_domain_name,
+                            custom_interface_id
+                        );
+                        match custom_interface_id.parse::<Ipv6Addr>() {
+                            Ok(interface_id) => Some(interface_id),
+                            Err(_) => {
+                                log::error!("The custom interface id for {} cannot be parsed into a valid ipv6 addr. Settings string: {}.", full_domain_name, custom_interface_id);
+                                return Err(NoAddress::Failed);
+                            }
+                        }
+                    }
+                    (None, Some(mac_address)) => match address::eui64_interface_id(mac_address) {
+                        Ok(interface_id) => {
+                            log::debug!(
+                                "The interface id for {} derived from {} is {}",
+                                full_domain_name,
+                                mac_address,
+                                interface_id
+                            );
+                            Some(interface_id)
+                        }
+                        Err(reason) => {
+                            log::error!(
+                                "The interface id for {} cannot be derived: {}",
+                                full_domain_name,
+                                reason
+                            );
+                            return Err(NoAddress::Failed);
+                        }
+                    },
+                    (None, None) => None,
+                };
+
+                let subnet_id = match &subdomain_settings.subnet_id {
+                    Some(subnet_id) => match address::parse_subnet_id(subnet_id) {
+                        Ok(subnet_id) => Some(subnet_id),
+                        Err(reason) => {
+                            log::error!(
+                                "The subnet id for {} is invalid: {}",
+                                full_domain_name,
+                                reason
+                            );
+                            return Err(NoAddress::Failed);
+                        }
+                    },
+                    None => None,
+                };
+
+                let composed_address = match address::compose_ipv6(
+                    v6_address,
+                    subdomain_settings.prefix_length,
+                    subdomain_settings.subnet_prefix_length,
+                    subnet_id,
+                    interface_id,
+                ) {
+                    Ok(address) => address,
+                    Err(reason) => {
+                        log::error!(
+                            "Composing the ip for {} failed: {}",
+                            full_domain_name,
+                            reason
+                        );
+                        return Err(NoAddress::Failed);
+                    }
+                };
+                if composed_address != v6_address {
+                    log::debug!(
+                        "The ip for {} will be {}, different from current machine's ip.",
+                        full_domain_name,
+                        composed_address
+                    );
+                }
+                composed_address.into()
+            }
+        };
+
+        // Static addresses are written by the user, only acquired addresses need the check.
+        if subdomain_settings.address.is_some() {
+            log::debug!(
+                "The ip for {} is the static address {}.",
+                full_domain_name,
+                content_ip
+            );
+        } else if let Err(reason) = self.address_filter.check(content_ip) {
+            log::error!(
+                "Refusing to publish {} record of {}: {}",
+                self.record_type,
+                full_domain_name,
+                reason
+            );
+            return Err(NoAddress::Failed);
+        }
+
+        Ok(content_ip)
+    }
+
+    /// Make the records of a subdomain publishing a set match the addresses from its `ip_sources`, with as few
+    /// changes as possible: records of stale addresses are reused for new ones before any is created or deleted.
+    /// If some addresses are not available, no record is deleted or reused.
+    fn update_record_set(
+        &mut self,
+        state: &Mutex<State>,
+        subdomain_settings: &SubDomainSettings,
+        full_domain_name: &str,
+    ) {
+        let family = self.record_type.address_families()[0];
+        let mut addresses: Vec<IpAddr> = Vec::new();
+        let mut complete = true;
+        let mut lost = false;
+        for ip_source in &subdomain_settings.ip_sources {
+            match self.source_address(subdomain_settings, full_domain_name, family, ip_source) {
+                Ok(address) => {
+                    if !addresses.contains(&address) {
+                        addresses.push(address);
+                    }
+                }
+                Err(NoAddress::Lost(ip_source)) => {
+                    log::error!(
+                        "No address from ip source {} for the {} records of {} as a result of previously failed ip address aquisition.",
+                        ip_source,
+                        self.record_type,
+                        full_domain_name
+                    );
+                    complete = false;
+                    lost = true;
+                }
+                Err(NoAddress::Failed) => complete = false,
+            }
+        }
+        if addresses.is_empty() {
+            if lost {
+                self.handle_address_loss(state, subdomain_settings, full_domain_name);
+            }
+            return;
+        }
+        if self.on_address_loss.is_some() {
+            lock(state).clear_missing_address_runs(self.record_type, full_domain_name);
+        }
+        for address in &addresses {
+            self.claim_ptr(*address, full_domain_name);
+        }
+
+        let records = match self.api_interface.describe_records(
+            &subdomain_settings.name,
+            &self.domain_name,
+            self.record_type,
+        ) {
+            Ok(records) => records,
+            Err(message) => {
+                log::error!(
+                    "Describe record for {} record of {} failed: {}",
+                    self.record_type,
+                    full_domain_name,
+                    message
+                );
+                return;
+            }
+        };
+
+        // Match the existing records with the addresses. Records of other addresses, or repeating an address, are
+        // stale.
+        let mut kept: Vec<RecordDetail> = Vec::new();
+        let mut stale: Vec<RecordDetail> = Vec::new();
+        for record_detail in records {
+            match &record_detail.content {
+                RecordContent::Address(address)
+                    if addresses.contains(address)
+                        && !kept
+                            .iter()
+                            .any(|kept| kept.content == record_detail.content) =>
+                {
+                    kept.push(record_detail)
+                }
+                _ => stale.push(record_detail),
+            }
+        }
+        let missing: Vec<IpAddr> = addresses
+            .iter()
+            .copied()
+            .filter(|address| {
+                !kept
+                    .iter()
+                    .any(|kept| kept.content == RecordContent::Address(*address))
+            })
+            .collect();
+        let target_record = |record_detail: Option<&RecordDetail>, address: IpAddr| {
+            let mut values = TemplateValues::new(full_domain_name);
+            values.set_address(address);
+            let content = RecordContent::Address(address);
+            let (comment, tags) =
+                record_labels(subdomain_settings, &values, &content, record_detail);
+            RecordDetail {
+                id: record_detail.and_then(|record| record.id.clone()),
+                subdomain_name: subdomain_settings.name.clone(),
+                base_domain_name: self.domain_name.clone(),
+                ttl: subdomain_settings.ttl,
+                proxied: subdomain_settings.proxied,
+                content,
+                comment,
+                tags,
+            }
+        };
+
+        // The addresses now in the set, with the address each one replaced, for the PTR records.
+        let mut changes: Vec<(IpAddr, Option<IpAddr>)> = Vec::new();
+        for record_detail in kept {
+            let RecordContent::Address(address) = record_detail.content else {
+                continue;
+            };
+            let target_record = target_record(Some(&record_detail), address);
+            if record_detail == target_record {
+                log::debug!(
+                    "{} record of {} for {} is same as current ip.",
+                    self.record_type,
+                    full_domain_name,
+                    address
+                );
+                if !lock(state).ptr_synced(address, full_domain_name) {
+                    changes.push((address, None));
+                }
+                continue;
+            }
+            match self.api_interface.update_record(target_record) {
+                Ok(_) => {
+                    log::info!(
+                        "Updated {} record of {} for {}.",
+                        self.record_type,
+                        full_domain_name,
+                        address
+                    );
+                    changes.push((address, None));
+                }
+                Err(message) => log::error!(
+                    "Update {} record of {} failed: {}",
+                    self.record_type,
+                    full_domain_name,
+                    message
+                ),
+            }
+        }
+
+        let mut stale = stale.into_iter();
+        for address in missing {
+            let reused = if complete { stale.next() } else { None };
+            match reused {
+                Some(record_detail) => {
+                    let old_address = match record_detail.content {
+                        RecordContent::Address(old_address) => Some(old_address),
+                        _ => None,
+                    };
+                    match self
+                        .api_interface
+                        .update_record(target_record(Some(&record_detail), address))
+                    {
+                        Ok(_) => {
+                            log::info!(
+                                "Updated {} record of {} to {}.",
+                                self.record_type,
+                                full_domain_name,
+                                address
+                            );
+                            changes.push((address, old_address));
+                        }
+                        Err(message) => log::error!(
+                            "Update {} record of {} failed: {}",
+                            self.record_type,
+                            full_domain_name,
+                            message
+                        ),
+                    }
+                }
+                None if self.create_new_record => {
+                    match self
+                        .api_interface
+                        .create_record(target_record(None, address))
+                    {
+                        Ok(_) => {
+                            log::info!(
+                                "Created new {} record of {} for {}.",
+                                self.record_type,
+                                full_domain_name,
+                                address
+                            );
+                            changes.push((address, None));
+                        }
+                        Err(message) => log::error!(
+                            "Create new {} record of {} failed: {}",
+                            self.record_type,
+                            full_domain_name,
+                            message
+                        ),
+                    }
+                }
+                None => log::info!(
+                    "{} record of {} for {} does not exist. Will not create new record.",
+                    self.record_type,
+                    full_domain_name,
+                    address
+                ),
+            }
+        }
+
+        let mut deleted = Vec::new();
+        for record_detail in stale {
+            let RecordContent::Address(old_address) = record_detail.content else {
+                continue;
+            };
+            if !complete {
+                log::info!(
+                    "{} record of {} for {} is kept until all addresses are acquired.",
+                    self.record_type,
+                    full_domain_name,
+                    old_address
+                );
+                continue;
+            }
+            match self.api_interface.delete_record(&record_detail) {
+                Ok(_) => {
+                    log::info!(
+                        "Deleted {} record of {} for {}.",
+                        self.record_type,
+                        full_domain_name,
+                        old_address
+                    );
+                    deleted.push(old_address);
+                }
+                Err(message) => log::error!(
+                    "Delete {} record of {} failed: {}",
+                    self.record_type,
+                    full_domain_name,
+                    message
+                ),
+            }
+        }
+
+        // A stale record may repeat an address still in the set, whose PTR record is kept.
+        for (address, old_address) in changes {
+            let old_address = old_address.filter(|old_address| !addresses.contains(old_address));
+            self.sync_ptr(
+                state,
+                subdomain_settings,
+                full_domain_name,
+                address,
+                old_address,
+            );
+        }
+        for old_address in deleted {
+            if !addresses.contains(&old_address) {
+                self.delete_ptr(state, full_domain_name, old_address);
+            }
+        }
+    }
+
+    /// Rewrite the `ipv4hint` and `ipv6hint` params of the existing HTTPS or SVCB record of a subdomain with its
+    /// addresses. Other params, the priority and the target are kept as they are, and hints that are missing are not
+    /// added.
+    fn update_service_hints(
+        &mut self,
+        subdomain_settings: &SubDomainSettings,
+        full_domain_name: &str,
+    ) {
+        let record_detail = match self.api_interface.describe_record(
+            &subdomain_settings.name,
+            &self.domain_name,
+            self.record_type,
+        ) {
+            Ok(Some(result)) => result,
+            Ok(None) => {
+                log::info!(
+                    "{} record for {} does not exist. Only the address hints of existing {} records are updated.",
+                    self.record_type,
+                    full_domain_name,
+                    self.record_type
+                );
+                return;
+            }
+            Err(message) => {
+                log::error!(
+                    "Describe record for {} record of {} failed: {}",
+                    self.record_type,
+                    full_domain_name,
+                    message
+                );
+                return;
+            }
+        };
+        let (RecordContent::Https(data) | RecordContent::Svcb(data)) = &record_detail.content
+        else {
+            log::error!(
+                "The api returned a record that is not {} for {}.",
+                self.record_type,
+                full_domain_name
+            );
+            return;
+        };
+
+        let mut data = data.clone();
+        for (family, key) in [
+            (AddressFamily::Ipv4, "ipv4hint"),
+            (AddressFamily::Ipv6, "ipv6hint"),
+        ] {
+            if !data.has_param(key) {
+                continue;
+            }
+            // A static address only provides the hint of its own family.
+            if subdomain_settings
+                .address
+                .is_some_and(|address| AddressFamily::from(address) != family)
+            {
+                continue;
+            }
+            match self.subdomain_address(subdomain_settings, full_domain_name, family) {
+                Ok(address) => data.set_param(key, &address.to_string()),
+                Err(NoAddress::Lost(ip_source)) => log::warn!(
+                    "No {} address from ip source {} for {}. Its {} is kept.",
+                    family,
+                    ip_source,
+                    full_domain_name,
+                    key
+                ),
+                Err(NoAddress::Failed) => {}
+            }
+        }
+
+        // The record should be like this
+        let target_record = RecordDetail {
+            id: record_detail.id.clone(),
+            subdomain_name: subdomain_settings.name.clone(),
+            base_domain_name: self.domain_name.clone(),
+            ttl: record_detail.ttl,
+            proxied: record_detail.proxied,
+            content: match record_detail.content {
+                RecordContent::Svcb(_) => RecordContent::Svcb(data),
+                _ => RecordContent::Https(data),
+            },
+            // The record is not created by the script, so its comment and tags are left alone.
+            comment: record_detail.comment.clone(),
+            tags: record_detail.tags.clone(),
+        };
+        if record_detail == target_record {
+            log::debug!(
+                "Address hints of {} record for {} are same as current ip.",
+                self.record_type,
+                full_domain_name
+            );
+            return;
+        }
+
+        match self.api_interface.update_record(target_record) {
+            Ok(_) => log::info!(
+                "Updated address hints of {} record of {}.",
+                self.record_type,
+                full_domain_name
+            ),
+            Err(message) => log::error!(
+                "Update {} record of {} failed: {}",
+                self.record_type,
+                full_domain_name,
+                message
+            ),
+        }
+    }
+
+    /// Create or update the TXT record of a subdomain from the template in `txt_record`. The record is only
+    /// rewritten when something other than the timestamp changes, so that the timestamp tells when the addresses
+    /// last changed. If an address used by the template is not available, the record is kept as is.
+    fn update_txt_record(
+        &mut self,
+        subdomain_settings: &SubDomainSettings,
+        full_domain_name: &str,
+    ) {
+        let Some(txt_record) = &self.txt_record else {
+            log::error!(
+                "{} records of {} need txt_record to be set.",
+                self.record_type,
+                self.domain_name
+            );
+            return;
+        };
+
+        let mut values = TemplateValues::new(full_domain_name);
+        for family in txt_record.content.address_families() {
+            // A static address only provides the address of its own family.
+            if subdomain_settings
+                .address
+                .is_some_and(|address| AddressFamily::from(address) != family)
+            {
+                continue;
+            }
+            match self.subdomain_address(subdomain_settings, full_domain_name, family) {
+                Ok(address) => values.set_address(address),
+                Err(NoAddress::Lost(ip_source)) => {
+                    log::warn!(
+                        "No {} address from ip source {} for {}. Its {} record is kept.",
+                        family,
+                        ip_source,
+                        full_domain_name,
+                        self.record_type
+                    );
+                    return;
+                }
+                Err(NoAddress::Failed) => return,
+            }
+        }
+
+        let record_name = txt_record.record_name(&subdomain_settings.name);
+        let full_record_name = format!("{}.{}", txt_record.prefix, full_domain_name);
+        let record_detail = match self.api_interface.describe_record(
+            &record_name,
+            &self.domain_name,
+            self.record_type,
+        ) {
+            Ok(result) => result,
+            Err(message) => {
+                log::error!(
+                    "Describe record for {} record of {} failed: {}",
+                    self.record_type,
+                    full_record_name,
+                    message
+                );
+                return;
+            }
+        };
+
+        // The record should be like this. A text only differing by the timestamp is kept.
+        let content = match record_detail.as_ref().map(|record| &record.content) {
+            Some(RecordContent::Text(text)) if txt_record.content.matches(text, &values) => {
+                RecordContent::Text(text.clone())
+            }
+            _ => RecordContent::Text(txt_record.content.render(&values)),
+        };
+        let (comment, tags) = record_labels(
+            subdomain_settings,
+            &values,
+            &content,
+            record_detail.as_ref(),
+        );
+        let target_record = RecordDetail {
+            id: record_detail.as_ref().and_then(|record| record.id.clone()),
+            subdomain_name: record_name,
+            base_domain_name: self.domain_name.clone(),
+            ttl: subdomain_settings.ttl,
+            proxied: None,
+            content,
+            comment,
+            tags,
+        };
+
+        let Some(record_detail) = record_detail else {
+            if !self.create_new_record {
+                log::info!(
+                    "{} record for {} does not exist. Will not create new record.",
+                    self.record_type,
+                    full_record_name
+                );
+                return;
+            }
+            log::info!(
+                "{} record for {} does not exist. Will create new record.",
+                self.record_type,
+                full_record_name
+            );
+            match self.api_interface.create_record(target_record) {
+                Ok(_) => log::info!(
+                    "Created new {} record of {}.",
+                    self.record_type,
+                    full_record_name
+                ),
+                Err(message) => log::error!(
+                    "Create new {} record of {} failed: {}",
+                    self.record_type,
+                    full_record_name,
+                    message
+                ),
+            }
+            return;
+        };
+
+        if record_detail == target_record {
+            log::debug!(
+                "{} record for {} is same as current ip.",
+                self.record_type,
+                full_record_name
+            );
+            return;
+        }
+
+        match self.api_interface.update_record(target_record) {
+            Ok(_) => log::info!(
+                "Updated {} record of {}.",
+                self.record_type,
+                full_record_name
+            ),
+            Err(message) => log::error!(
+                "Update {} record of {} failed: {}",
+                self.record_type,
+                full_record_name,
+                message
+            ),
+        }
+    }
+
+    /// Point the PTR record of `address` at `full_domain_name` unless another subdomain keeps it, and delete the one
+    /// of `old_address` if it still points there. The PTR records found or made to point at their name are
+    /// remembered in `state`, so that they are not checked again while the A or AAAA record stays the same.
+    fn sync_ptr(
+        &mut self,
+        state: &Mutex<State>,
+        subdomain_settings: &SubDomainSettings,
+        full_domain_name: &str,
+        address: IpAddr,
+        old_address: Option<IpAddr>,
+    ) {
+        match self.ptr_owners.get(&address) {
+            Some(owner) if owner != full_domain_name => log::debug!(
+                "PTR record of {} is kept by {}, which publishes the same address first.",
+                address,
+                owner
+            ),
+            _ => self.update_ptr(state, subdomain_settings, full_domain_name, address),
+        }
+        if let Some(old_address) = old_address.filter(|old_address| *old_address != address) {
+            self.delete_ptr(state, full_domain_name, old_address);
+        }
+    }
+
+    /// Make `full_domain_name` keep the PTR record of `address`, unless a subdomain before it in this run publishes
+    /// the same address.
+    fn claim_ptr(&mut self, address: IpAddr, full_domain_name: &str) {
+        self.ptr_owners
+            .entry(address)
+            .or_insert_with(|| full_domain_name.to_string());
+    }
+
+    /// Create or update the PTR record of `address` to point at `full_domain_name`. Addresses outside the reverse
+    /// zones are skipped.
+    fn update_ptr(
+        &mut self,
+        state: &Mutex<State>,
+        subdomain_settings: &SubDomainSettings,
+        full_domain_name: &str,
+        address: IpAddr,
+    ) {
+        let Some((zone, record_name)) = self.reverse_zone(address) else {
+            if !self.reverse_zones.is_empty() {
+                log::debug!(
+                    "{} is not in any reverse zone, its PTR record is not managed.",
+                    address
+                );
+            }
+            return;
+        };
+        let zone = &mut self.reverse_zones[zone];
+        let record_detail =
+            match zone
+                .api_interface
+                .describe_record(&record_name, &zone.name, RecordType::Ptr)
+            {
+                Ok(result) => result,
+                Err(message) => {
+                    log::error!(
+                        "Describe record for PTR record of {} failed: {}",
+                        address,
+                        message
+                    );
+                    return;
+                }
+            };
+        let mut values = TemplateValues::new(full_domain_name);
+        values.set_address(address);
+        let content = RecordContent::Pointer(full_domain_name.to_string());
+        let (comment, tags) = record_labels(
+            subdomain_settings,
+            &values,
+            &content,
+            record_detail.as_ref(),
+        );
+        let target_record = RecordDetail {
+            id: record_detail.as_ref().and_then(|record| record.id.clone()),
+            subdomain_name: record_name,
+            base_domain_name: zone.name.clone(),
+            ttl: subdomain_settings.ttl,
+            proxied: None,
+            content,
+            comment,
+            tags,
+        };
+        match record_detail {
+            None => match zone.api_interface.create_record(target_record) {
+                Ok(_) => {
+                    log::info!(
+                        "Created new PTR record of {} for {}.",
+                        address,
+                        full_domain_name
+                    );
+                    lock(state).set_ptr_synced(address, full_domain_name);
+                }
+                Err(message) => {
+                    log::error!("Create new PTR record of {} failed: {}", address, message)
+                }
+            },
+            Some(record_detail) if record_detail == target_record => {
+                log::debug!("PTR record of {} is up to date.", address);
+                lock(state).set_ptr_synced(address, full_domain_name);
+            }
+            Some(_) => match zone.api_interface.update_record(target_record) {
+                Ok(_) => {
+                    log::info!("Updated PTR record of {} to {}.", address, full_domain_name);
+                    lock(state).set_ptr_synced(address, full_domain_name);
+                }
+                Err(message) => {
+                    log::error!("Update PTR record of {} failed: {}", address, message)
+                }
+            },
+        }
+    }
+
+    /// Delete the PTR record of `old_address` if it still points at `full_domain_name`.
+    fn delete_ptr(&mut self, state: &Mutex<State>, full_domain_name: &str, old_address: IpAddr) {
+        let Some((zone, record_name)) = self.reverse_zone(old_address) else {
+            return;
+        };
+        let zone = &mut self.reverse_zones[zone];
+        match zone
+            .api_interface
+            .describe_record(&record_name, &zone.name, RecordType::Ptr)
+        {
+            Ok(Some(record_detail)) if matches!(&record_detail.content, RecordContent::Pointer(target) if same_name(target, full_domain_name)) => {
+                match zone.api_interface.delete_record(&record_detail) {
+                    Ok(_) => {
+                        log::info!("Deleted old PTR record of {}.", old_address);
+                        lock(state).clear_ptr_synced(old_address);
+                    }
+                    Err(message) => log::error!(
+                        "Delete old PTR record of {} failed: {}",
+                        old_address,
+                        message
+                    ),
+                }
+            }
+            Ok(_) => {
+                log::debug!(
+                    "Old PTR record of {} does not point to {}, it is kept.",
+                    old_address,
+                    full_domain_name
+                );
+                lock(state).clear_ptr_synced(old_address);
+            }
+            Err(message) => log::error!(
+                "Describe record for PTR record of {} failed: {}",
+                old_address,
+                message
+            ),
+        }
+    }
+
+    /// Find the reverse zone holding the PTR record of `address`, returning its index and the name of the record
+    /// relative to it. The longest matching zone is used.
+    fn reverse_zone(&self, address: IpAddr) -> Option<(usize, String)> {
+        let reverse_name = address::reverse_name(address);
+        self.reverse_zones
+            .iter()
+            .enumerate()
+            .filter_map(|(index, zone)| {
+                let record_name = reverse_name.strip_suffix(&zone.name)?;
+                match record_name.strip_suffix('.') {
+                    Some(record_name) => Some((index, zone.name.len(), record_name.to_string())),
+                    None if record_name.is_empty() => {
+                        Some((index, zone.name.len(), "@".to_string()))
+                    }
+                    None => None,
+                }
+            })
+            .max_by_key(|(_, zone_length, _)| *zone_length)
+            .map(|(index, _, record_name)| (index, record_name))
+    }
+
+    /// Count a run in which the address of the record could not be acquired, and delete or park the record if
+    /// `on_address_loss` is set and it has happened for enough runs in a row. The PTR records of the addresses taken
+    /// out are deleted.
+    fn handle_address_loss(
+        &mut self,
+        state: &Mutex<State>,
+        subdomain_settings: &SubDomainSettings,
+        full_domain_name: &str,
+    ) {
+        let Some(policy) = &self.on_address_loss else {
+            return;
+        };
+
+        let runs = lock(state).add_missing_address_run(self.record_type, full_domain_name);
+        if runs < policy.after_runs {
+            log::info!(
+                "No address for {} record of {} in {} runs in a row. It will be handled after {} runs.",
+                self.record_type,
+                full_domain_name,
+                runs,
+                policy.after_runs
+            );
+            return;
+        }
+
+        // A subdomain publishing a set of addresses may have several records.
+        let records = match self.api_interface.describe_records(
+            &subdomain_settings.name,
+            &self.domain_name,
+            self.record_type,
+        ) {
+            Ok(records) if records.is_empty() => {
+                log::debug!(
+                    "{} record for {} does not exist. Nothing to do for the lost address.",
+                    self.record_type,
+                    full_domain_name
+                );
+                return;
+            }
+            Ok(records) => records,
+            Err(message) => {
+                log::error!(
+                    "Describe record for {} record of {} failed: {}",
+                    self.record_type,
+                    full_domain_name,
+                    message
+                );
+                return;
+            }
+        };
+
+        let mut records_to_delete = records;
+        if let AddressLossAction::Park { .. } = &policy.action {
+            let Some(park_address) = policy.action.park_address(self.record_type) else {
+                log::error!(
+                    "No {} address to park {} at is configured.",
+                    self.record_type,
+                    full_domain_name
+                );
+                return;
+            };
+            // One record is parked and the rest of the set is deleted.
+            let record_detail = records_to_delete.remove(0);
+            let mut values = TemplateValues::new(full_domain_name);
+            values.set_address(park_address);
+            let content = RecordContent::Address(park_address);
+            let (comment, tags) =
+                record_labels(subdomain_settings, &values, &content, Some(&record_detail));
+            let target_record = RecordDetail {
+                id: record_detail.id.clone(),
+                subdomain_name: subdomain_settings.name.clone(),
+                base_domain_name: self.domain_name.clone(),
+                ttl: subdomain_settings.ttl,
+                proxied: subdomain_settings.proxied,
+                content,
+                comment,
+                tags,
+            };
+            if record_detail == target_record {
+                log::debug!(
+                    "{} record for {} is already parked.",
+                    self.record_type,
+                    full_domain_name
+                );
+            } else {
+                match self.api_interface.update_record(target_record) {
+                    Ok(_) => {
+                        log::info!(
+                            "Parked {} record of {} at {} after {} runs without an address.",
+                            self.record_type,
+                            full_domain_name,
+                            park_address,
+                            runs
+                        );
+                        if let RecordContent::Address(old_address) = record_detail.content {
+                            self.delete_ptr(state, full_domain_name, old_address);
+                        }
+                    }
+                    Err(message) => log::error!(
+                        "Update {} record of {} failed: {}",
+                        self.record_type,
+                        full_domain_name,
+                        message
+                    ),
+                }
+            }
+        }
+
+        for record_detail in records_to_delete {
+            match self.api_interface.delete_record(&record_detail) {
+                Ok(_) => {
+                    log::info!(
+                        "Deleted {} record of {} after {} runs without an address.",
+                        self.record_type,
+                        full_domain_name,
+                        runs
+                    );
+                    if let RecordContent::Address(old_address) = record_detail.content {
+                        self.delete_ptr(state, full_domain_name, old_address);
+                    }
+                }
+                Err(message) => log::error!(
+                    "Delete {} record of {} failed: {}",
+                    self.record_type,
+                    full_domain_name,
+                    message
+                ),
+            }
+        }
     }
 }
+
+/// The comment and tags of a record of `subdomain_settings` holding `content`, replacing `record_detail` if it exists.
+/// The comment is rendered from the template with `values`, but an existing comment only differing by the timestamp
+/// is kept while the content stays the same. A comment or tags not set for the subdomain are kept as they are.
+fn record_labels(
+    subdomain_settings: &SubDomainSettings,
+    values: &TemplateValues,
+    content: &RecordContent,
+    record_detail: Option<&RecordDetail>,
+) -> (Option<String>, Option<Vec<String>>) {
+    let existing_comment = record_detail.and_then(|record| record.comment.clone());
+    let comment = match &subdomain_settings.comment {
+        Some(template) => match existing_comment {
+            Some(comment)
+                if record_detail.is_some_and(|record| record.content == *content)
+                    && template.matches(&comment, values) =>
+            {
+                Some(comment)
+            }
+            _ => Some(template.render(values)).filter(|comment| !comment.is_empty()),
+        },
+        None => existing_comment,
+    };
+    let tags = match &subdomain_settings.tags {
+        Some(tags) => Some(tags.clone()),
+        None => record_detail.and_then(|record| record.tags.clone()),
+    };
+    (comment, tags)
+}
+
+/// Compare domain names, ignoring case and the trailing dot.
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
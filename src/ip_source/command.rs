@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use std::io::Read;
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{extract::ExtractRule, AddressFamily};
+
+/// Get the address by running a command and reading the address from its standard output with `extract`. A
+/// non-zero exit status is a failure.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CommandSourceConfig {
+    /// The program to run. It is not run through a shell.
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds to wait for the command before killing it.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default)]
+    pub extract: ExtractRule,
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl CommandSourceConfig {
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        log::trace!(
+            "Running {} {} for {} address",
+            self.command,
+            self.args.join(" "),
+            family
+        );
+
+        let mut child = match Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(reason) => return Err(format!("Error starting {}: {}", self.command, reason)),
+        };
+
+        // Read the outputs in other threads, so that a command writing a lot does not block on a full pipe.
+        let read_all = |pipe: Option<Box<dyn Read + Send>>| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let mut content = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut content);
+                }
+                let _ = sender.send(String::from_utf8_lossy(&content).into_owned());
+            });
+            receiver
+        };
+        let stdout = read_all(
+            child
+                .stdout
+                .take()
+                .map(|pipe| Box::new(pipe) as Box<dyn Read + Send>),
+        );
+        let stderr = read_all(
+            child
+                .stderr
+                .take()
+                .map(|pipe| Box::new(pipe) as Box<dyn Read + Send>),
+        );
+
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!(
+                        "{} did not finish within {} seconds and is killed",
+                        self.command, self.timeout
+                    ));
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(50)),
+                Err(reason) => {
+                    return Err(format!("Error waiting for {}: {}", self.command, reason))
+                }
+            }
+        };
+
+        // A process started in the background by the command keeps the pipes open after the command exits, so the
+        // outputs are only waited for until the deadline.
+        let read = |receiver: Receiver<String>| {
+            receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        };
+        let stdout = match read(stdout) {
+            Ok(stdout) => stdout,
+            Err(_) => {
+                return Err(format!(
+                    "The output of {} is not closed within {} seconds, e.g. by a process it left running",
+                    self.command, self.timeout
+                ))
+            }
+        };
+        let stderr = read(stderr).unwrap_or_default();
+        log::trace!("{} returned stdout: {}", self.command, stdout);
+        if !stderr.is_empty() {
+            log::trace!("{} returned stderr: {}", self.command, stderr);
+        }
+
+        if !status.success() {
+            return Err(format!(
+                "{} exited with {}: {}",
+                self.command,
+                status,
+                stderr.trim()
+            ));
+        }
+
+        self.extract.extract(&stdout, family)
+    }
+}
@@ -0,0 +1,456 @@
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::AddressFamily;
+
+/// Get the address by asking a dns server that answers a special name with the address the query came from.
+///
+/// Either `preset` or both `name` and `resolver` should be provided. Fields that are provided override the
+/// ones from the preset.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DnsSourceConfig {
+    pub preset: Option<DnsPreset>,
+    pub name: Option<String>,
+    /// The address of the dns server, optionally with a port, e.g. `208.67.222.222` or `[::1]:5353`. The query
+    /// is sent over the address family being acquired, so the resolver must be of that family.
+    pub resolver: Option<String>,
+    pub query_type: Option<DnsQueryType>,
+    pub class: Option<DnsClass>,
+    /// Seconds to wait for the answer.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+/// Well known services answering with the address of the client.
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsPreset {
+    /// `myip.opendns.com` A/AAAA at resolver1.opendns.com.
+    Opendns,
+    /// `whoami.cloudflare` TXT in the CHAOS class at 1.1.1.1.
+    Cloudflare,
+    /// `o-o.myaddr.l.google.com` TXT at ns1.google.com.
+    Google,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DnsQueryType {
+    A,
+    #[serde(rename = "AAAA")]
+    Aaaa,
+    #[serde(rename = "TXT")]
+    Txt,
+}
+
+impl DnsQueryType {
+    fn code(&self) -> u16 {
+        match self {
+            DnsQueryType::A => 1,
+            DnsQueryType::Aaaa => 28,
+            DnsQueryType::Txt => 16,
+        }
+    }
+}
+
+impl std::fmt::Display for DnsQueryType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DnsQueryType::A => write!(f, "A"),
+            DnsQueryType::Aaaa => write!(f, "AAAA"),
+            DnsQueryType::Txt => write!(f, "TXT"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub enum DnsClass {
+    #[serde(rename = "IN")]
+    Internet,
+    #[serde(rename = "CH")]
+    Chaos,
+}
+
+impl DnsClass {
+    fn code(&self) -> u16 {
+        match self {
+            DnsClass::Internet => 1,
+            DnsClass::Chaos => 3,
+        }
+    }
+}
+
+/// The fully resolved query to send.
+struct DnsQuery {
+    name: String,
+    resolver: SocketAddr,
+    query_type: DnsQueryType,
+    class: DnsClass,
+}
+
+impl DnsPreset {
+    fn name(&self) -> &'static str {
+        match self {
+            DnsPreset::Opendns => "myip.opendns.com",
+            DnsPreset::Cloudflare => "whoami.cloudflare",
+            DnsPreset::Google => "o-o.myaddr.l.google.com",
+        }
+    }
+
+    fn resolver(&self, family: AddressFamily) -> IpAddr {
+        match (self, family) {
+            (DnsPreset::Opendns, AddressFamily::Ipv4) => Ipv4Addr::new(208, 67, 222, 222).into(),
+            (DnsPreset::Opendns, AddressFamily::Ipv6) => {
+                Ipv6Addr::new(0x2620, 0x119, 0x35, 0, 0, 0, 0, 0x35).into()
+            }
+            (DnsPreset::Cloudflare, AddressFamily::Ipv4) => Ipv4Addr::new(1, 1, 1, 1).into(),
+            (DnsPreset::Cloudflare, AddressFamily::Ipv6) => {
+                Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111).into()
+            }
+            (DnsPreset::Google, AddressFamily::Ipv4) => Ipv4Addr::new(216, 239, 32, 10).into(),
+            (DnsPreset::Google, AddressFamily::Ipv6) => {
+                Ipv6Addr::new(0x2001, 0x4860, 0x4802, 0x32, 0, 0, 0, 0xa).into()
+            }
+        }
+    }
+
+    fn query_type(&self, family: AddressFamily) -> DnsQueryType {
+        match (self, family) {
+            (DnsPreset::Opendns, AddressFamily::Ipv4) => DnsQueryType::A,
+            (DnsPreset::Opendns, AddressFamily::Ipv6) => DnsQueryType::Aaaa,
+            (DnsPreset::Cloudflare | DnsPreset::Google, _) => DnsQueryType::Txt,
+        }
+    }
+
+    fn class(&self) -> DnsClass {
+        match self {
+            DnsPreset::Cloudflare => DnsClass::Chaos,
+            DnsPreset::Opendns | DnsPreset::Google => DnsClass::Internet,
+        }
+    }
+}
+
+impl DnsSourceConfig {
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        let query = self.build_query(family)?;
+        log::trace!(
+            "Querying {} record of {} at {} for {} address",
+            query.query_type,
+            query.name,
+            query.resolver,
+            family
+        );
+
+        let id = query_id();
+        let request = encode_query(id, &query)?;
+
+        let bind_address: SocketAddr = match family {
+            AddressFamily::Ipv4 => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            AddressFamily::Ipv6 => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = match UdpSocket::bind(bind_address) {
+            Ok(socket) => socket,
+            Err(reason) => return Err(format!("Error binding udp socket: {}", reason)),
+        };
+        if let Err(reason) = socket.set_read_timeout(Some(Duration::from_secs(self.timeout))) {
+            return Err(format!("Error setting socket timeout: {}", reason));
+        }
+        if let Err(reason) = socket.send_to(&request, query.resolver) {
+            return Err(format!(
+                "Error sending dns query to {}: {}",
+                query.resolver, reason
+            ));
+        }
+
+        let mut buffer = [0u8; 1500];
+        loop {
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(reason)
+                    if matches!(
+                        reason.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(format!(
+                        "No dns answer from {} within {} seconds",
+                        query.resolver, self.timeout
+                    ))
+                }
+                Err(reason) => {
+                    return Err(format!(
+                        "Error receiving dns answer from {}: {}",
+                        query.resolver, reason
+                    ))
+                }
+            };
+            let response = &buffer[..length];
+            // Ignore anything that is not the answer to our query.
+            if from != query.resolver || response.len() < 2 || response[0..2] != id.to_be_bytes() {
+                log::trace!("Ignoring unrelated datagram from {}", from);
+                continue;
+            }
+            return parse_answer(response, &query, family);
+        }
+    }
+
+    fn build_query(&self, family: AddressFamily) -> Result<DnsQuery, String> {
+        let name = match (&self.name, self.preset) {
+            (Some(name), _) => name.clone(),
+            (None, Some(preset)) => preset.name().to_string(),
+            (None, None) => return Err("Neither name nor preset is provided.".to_string()),
+        };
+
+        let resolver = match (&self.resolver, self.preset) {
+            (Some(resolver), _) => match resolver.parse::<SocketAddr>() {
+                Ok(address) => address,
+                Err(_) => match resolver.parse::<IpAddr>() {
+                    Ok(address) => SocketAddr::new(address, 53),
+                    Err(_) => return Err(format!("Invalid resolver address: {}", resolver)),
+                },
+            },
+            (None, Some(preset)) => SocketAddr::new(preset.resolver(family), 53),
+            (None, None) => return Err("Neither resolver nor preset is provided.".to_string()),
+        };
+        if AddressFamily::from(resolver.ip()) != family {
+            return Err(format!(
+                "Resolver {} is not an {} address, so the answer would not be the {} address.",
+                resolver, family, family
+            ));
+        }
+
+        let query_type = match (self.query_type, self.preset) {
+            (Some(query_type), _) => query_type,
+            (None, Some(preset)) => preset.query_type(family),
+            (None, None) => match family {
+                AddressFamily::Ipv4 => DnsQueryType::A,
+                AddressFamily::Ipv6 => DnsQueryType::Aaaa,
+            },
+        };
+
+        let class = match (self.class, self.preset) {
+            (Some(class), _) => class,
+            (None, Some(preset)) => preset.class(),
+            (None, None) => DnsClass::Internet,
+        };
+
+        Ok(DnsQuery {
+            name,
+            resolver,
+            query_type,
+            class,
+        })
+    }
+}
+
+// Queries are not security sensitive here, the id only needs to tell our answer apart from stray datagrams.
+fn query_id() -> u16 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or(0);
+    (nanos ^ std::process::id()) as u16
+}
+
+fn encode_query(id: u16, query: &DnsQuery) -> Result<Vec<u8>, String> {
+    let mut message = Vec::with_capacity(512);
+    message.extend_from_slice(&id.to_be_bytes());
+    // Flags: standard query with recursion desired.
+    message.extend_from_slice(&0x0100u16.to_be_bytes());
+    // One question, no answer, authority or additional records.
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in query.name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid dns name: {}", query.name));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+
+    message.extend_from_slice(&query.query_type.code().to_be_bytes());
+    message.extend_from_slice(&query.class.code().to_be_bytes());
+    Ok(message)
+}
+
+fn read_u16(message: &[u8], position: usize) -> Result<u16, String> {
+    match message.get(position..position + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err("Dns answer is truncated.".to_string()),
+    }
+}
+
+/// Return the position right after the (possibly compressed) name starting at `position`.
+fn skip_name(message: &[u8], mut position: usize) -> Result<usize, String> {
+    loop {
+        let length = match message.get(position) {
+            Some(length) => *length,
+            None => return Err("Dns answer is truncated.".to_string()),
+        };
+        match length {
+            0 => return Ok(position + 1),
+            // A compression pointer takes two bytes and ends the name.
+            length if length & 0xc0 == 0xc0 => return Ok(position + 2),
+            length => position += 1 + length as usize,
+        }
+    }
+}
+
+fn parse_answer(message: &[u8], query: &DnsQuery, family: AddressFamily) -> Result<IpAddr, String> {
+    let flags = read_u16(message, 2)?;
+    if flags & 0x8000 == 0 {
+        return Err("Dns message is not an answer.".to_string());
+    }
+    if flags & 0x0200 != 0 {
+        return Err("Dns answer is truncated.".to_string());
+    }
+    let response_code = flags & 0x000f;
+    if response_code != 0 {
+        return Err(format!("Dns server returned error code {}", response_code));
+    }
+
+    let question_count = read_u16(message, 4)?;
+    let answer_count = read_u16(message, 6)?;
+
+    let mut position = 12;
+    for _ in 0..question_count {
+        // Name, type and class.
+        position = skip_name(message, position)? + 4;
+    }
+
+    for _ in 0..answer_count {
+        position = skip_name(message, position)?;
+        let record_type = read_u16(message, position)?;
+        let data_length = read_u16(message, position + 8)? as usize;
+        let data_start = position + 10;
+        let data = match message.get(data_start..data_start + data_length) {
+            Some(data) => data,
+            None => return Err("Dns answer is truncated.".to_string()),
+        };
+        position = data_start + data_length;
+
+        if record_type != query.query_type.code() {
+            continue;
+        }
+
+        let address: Option<IpAddr> = match query.query_type {
+            DnsQueryType::A => <[u8; 4]>::try_from(data).ok().map(IpAddr::from),
+            DnsQueryType::Aaaa => <[u8; 16]>::try_from(data).ok().map(IpAddr::from),
+            DnsQueryType::Txt => {
+                // TXT data is a sequence of length prefixed strings.
+                let mut text = String::new();
+                let mut text_position = 0;
+                while let Some(length) = data.get(text_position) {
+                    let end = (text_position + 1 + *length as usize).min(data.len());
+                    text.push_str(&String::from_utf8_lossy(&data[text_position + 1..end]));
+                    text_position = end;
+                }
+                log::trace!("Dns TXT answer: {}", text);
+                family.parse_address(text.trim().trim_matches('"')).ok()
+            }
+        };
+
+        match address {
+            Some(address) if AddressFamily::from(address) == family => return Ok(address),
+            Some(address) => log::trace!("Ignoring answer {} of another family", address),
+            None => log::trace!("Ignoring dns answer that is not an {} address", family),
+        }
+    }
+
+    Err(format!(
+        "No {} address in the answer for {} from {}",
+        family, query.name, query.resolver
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(query_type: DnsQueryType) -> DnsQuery {
+        DnsQuery {
+            name: "myip.example.com".to_string(),
+            resolver: "127.0.0.1:53".parse().unwrap(),
+            query_type,
+            class: DnsClass::Internet,
+        }
+    }
+
+    /// An answer to `query` with one record of `record_type` holding `data`, the name compressed to the question.
+    fn answer(query: &DnsQuery, record_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut message = encode_query(0x1234, query).unwrap();
+        // Flags: answer with recursion desired and available, one answer.
+        message[2..4].copy_from_slice(&0x8180u16.to_be_bytes());
+        message[6..8].copy_from_slice(&1u16.to_be_bytes());
+        message.extend_from_slice(&[0xc0, 12]);
+        message.extend_from_slice(&record_type.to_be_bytes());
+        message.extend_from_slice(&1u16.to_be_bytes());
+        message.extend_from_slice(&60u32.to_be_bytes());
+        message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        message.extend_from_slice(data);
+        message
+    }
+
+    #[test]
+    fn parse_a_answer() {
+        let query = query(DnsQueryType::A);
+        let message = answer(&query, 1, &[192, 0, 2, 1]);
+        assert_eq!(
+            parse_answer(&message, &query, AddressFamily::Ipv4),
+            Ok("192.0.2.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_aaaa_answer() {
+        let query = query(DnsQueryType::Aaaa);
+        let address: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let message = answer(&query, 28, &address.octets());
+        assert_eq!(
+            parse_answer(&message, &query, AddressFamily::Ipv6),
+            Ok(address.into())
+        );
+    }
+
+    #[test]
+    fn parse_txt_answer() {
+        let query = query(DnsQueryType::Txt);
+        let mut data = vec![9];
+        data.extend_from_slice(b"192.0.2.1");
+        let message = answer(&query, 16, &data);
+        assert_eq!(
+            parse_answer(&message, &query, AddressFamily::Ipv4),
+            Ok("192.0.2.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_truncated_answer() {
+        let query = query(DnsQueryType::A);
+        let message = answer(&query, 1, &[192, 0, 2, 1]);
+        for length in [3, 12, message.len() - 1] {
+            assert!(
+                parse_answer(&message[..length], &query, AddressFamily::Ipv4).is_err(),
+                "{}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn parse_error_answer() {
+        let query = query(DnsQueryType::A);
+        let mut message = answer(&query, 1, &[192, 0, 2, 1]);
+        // NXDOMAIN.
+        message[3] |= 3;
+        assert_eq!(
+            parse_answer(&message, &query, AddressFamily::Ipv4),
+            Err("Dns server returned error code 3".to_string())
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use serde::Deserialize;
+use std::net::IpAddr;
+
+use super::AddressFamily;
+
+/// How the address is read from the text returned by a source.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExtractRule {
+    /// The whole text, with surrounding whitespace trimmed, is the address.
+    #[default]
+    Plain,
+    /// The text is a json document and the address is the string at `pointer`, e.g. `/ip`.
+    JsonPointer { pointer: String },
+    /// The address is captured by `pattern`. The capture group named `ip` is used if it exists, otherwise the
+    /// first capture group, otherwise the whole match.
+    Regex { pattern: String },
+    /// The address is the first token in the text that parses as an address of the requested family.
+    FirstIp,
+}
+
+impl std::fmt::Display for ExtractRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractRule::Plain => write!(f, "plain"),
+            ExtractRule::JsonPointer { pointer } => write!(f, "json_pointer {}", pointer),
+            ExtractRule::Regex { pattern } => write!(f, "regex {}", pattern),
+            ExtractRule::FirstIp => write!(f, "first_ip"),
+        }
+    }
+}
+
+impl ExtractRule {
+    /// Read an address of `family` from `content`. If failed, return a string specify the rule and the reason.
+    pub fn extract(&self, content: &str, family: AddressFamily) -> Result<IpAddr, String> {
+        match self.extract_inner(content, family) {
+            Ok(address) => Ok(address),
+            Err(reason) => Err(format!("Extract rule {} failed: {}", self, reason)),
+        }
+    }
+
+    fn extract_inner(&self, content: &str, family: AddressFamily) -> Result<IpAddr, String> {
+        match self {
+            ExtractRule::Plain => family.parse_address(content.trim()),
+            ExtractRule::JsonPointer { pointer } => {
+                let document: serde_json::Value = match serde_json::from_str(content) {
+                    Ok(document) => document,
+                    Err(reason) => return Err(format!("Error parse json: {}", reason)),
+                };
+                match document.pointer(pointer) {
+                    Some(serde_json::Value::String(value)) => family.parse_address(value.trim()),
+                    Some(value) => Err(format!("Value at {} is not a string: {}", pointer, value)),
+                    None => Err(format!("Nothing found at {}", pointer)),
+                }
+            }
+            ExtractRule::Regex { pattern } => {
+                let regex = match regex::Regex::new(pattern) {
+                    Ok(regex) => regex,
+                    Err(reason) => return Err(format!("Invalid pattern: {}", reason)),
+                };
+                let captures = match regex.captures(content) {
+                    Some(captures) => captures,
+                    None => return Err("Pattern does not match".to_string()),
+                };
+                let matched = captures
+                    .name("ip")
+                    .or_else(|| captures.get(1))
+                    .or_else(|| captures.get(0))
+                    .expect("Group 0 always exists for a match");
+                family.parse_address(matched.as_str().trim())
+            }
+            ExtractRule::FirstIp => {
+                // A colon never belongs to an IPv4 address, so `ip:192.0.2.1` splits before the address.
+                let is_address_char = |c: char| match family {
+                    AddressFamily::Ipv4 => c.is_ascii_digit() || c == '.',
+                    AddressFamily::Ipv6 => c.is_ascii_hexdigit() || c == '.' || c == ':',
+                };
+                content
+                    .split(|c: char| !is_address_char(c))
+                    .filter(|token| !token.is_empty())
+                    .find_map(|token| {
+                        // Tokens at the end of a sentence or around a label or a port may carry separators.
+                        [
+                            token,
+                            token.trim_end_matches(['.', ':']),
+                            token.trim_start_matches(['.', ':']),
+                            token.trim_matches(['.', ':']),
+                        ]
+                        .into_iter()
+                        .find_map(|candidate| family.parse_address(candidate).ok())
+                    })
+                    .ok_or_else(|| format!("No {} address found in content", family))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_ip(content: &str, family: AddressFamily) -> Result<IpAddr, String> {
+        ExtractRule::FirstIp.extract(content, family)
+    }
+
+    #[test]
+    fn first_ip_after_a_label() {
+        for content in [
+            "ip:192.0.2.1",
+            "addr:192.0.2.1",
+            "Current IP Address: 192.0.2.1",
+        ] {
+            assert_eq!(
+                first_ip(content, AddressFamily::Ipv4),
+                Ok("192.0.2.1".parse().unwrap()),
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn first_ip_skips_other_family_and_separators() {
+        let content = "Your address is 2001:db8::1, or 192.0.2.1.";
+        assert_eq!(
+            first_ip(content, AddressFamily::Ipv4),
+            Ok("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            first_ip(content, AddressFamily::Ipv6),
+            Ok("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(
+            first_ip("[::1]:8080", AddressFamily::Ipv6),
+            Ok("::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn first_ip_without_address() {
+        assert!(first_ip("no address here", AddressFamily::Ipv4).is_err());
+        assert!(first_ip("2001:db8::1", AddressFamily::Ipv4).is_err());
+    }
+}
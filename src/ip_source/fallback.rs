@@ -0,0 +1,218 @@
+use serde::Deserialize;
+use std::net::IpAddr;
+
+use super::{AddressFamily, IpSource};
+
+/// Get the address from a list of sources. The sources are tried in order and the first address acquired is
+/// used. If `required_agreement` is set, all the sources are queried and an address is only trusted when at
+/// least that many sources returned it.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FallbackSourceConfig {
+    pub sources: Vec<IpSource>,
+    pub required_agreement: Option<usize>,
+}
+
+impl FallbackSourceConfig {
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        if self.sources.is_empty() {
+            return Err("No source is provided.".to_string());
+        }
+
+        match self.required_agreement {
+            None => self.get_first_address(family),
+            Some(required_agreement) => self.get_agreed_address(family, required_agreement),
+        }
+    }
+
+    fn get_first_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        for source in &self.sources {
+            match source.get_address(family) {
+                Ok(address) => {
+                    log::debug!("Got {} address {} from {}", family, address, source);
+                    return Ok(address);
+                }
+                Err(reason) => log::warn!(
+                    "Get {} address from {} failed: {}. Trying next source.",
+                    family,
+                    source,
+                    reason
+                ),
+            }
+        }
+        Err(format!("All {} sources failed.", self.sources.len()))
+    }
+
+    fn get_agreed_address(
+        &self,
+        family: AddressFamily,
+        required_agreement: usize,
+    ) -> Result<IpAddr, String> {
+        if required_agreement == 0 || required_agreement > self.sources.len() {
+            return Err(format!(
+                "required_agreement must be between 1 and the number of sources ({}), got {}.",
+                self.sources.len(),
+                required_agreement
+            ));
+        }
+
+        // Every distinct address returned and the sources that returned it, in the order first seen.
+        let mut votes: Vec<(IpAddr, Vec<String>)> = Vec::new();
+        for source in &self.sources {
+            match source.get_address(family) {
+                Ok(address) => {
+                    log::debug!("Got {} address {} from {}", family, address, source);
+                    match votes.iter_mut().find(|(voted, _)| *voted == address) {
+                        Some((_, voters)) => voters.push(source.to_string()),
+                        None => votes.push((address, vec![source.to_string()])),
+                    }
+                }
+                Err(reason) => {
+                    log::warn!("Get {} address from {} failed: {}", family, source, reason)
+                }
+            }
+        }
+
+        let describe_votes = || {
+            votes
+                .iter()
+                .map(|(address, voters)| format!("{} from [{}]", address, voters.join(", ")))
+                .collect::<Vec<String>>()
+                .join("; ")
+        };
+
+        let agreed: Vec<&IpAddr> = votes
+            .iter()
+            .filter(|(_, voters)| voters.len() >= required_agreement)
+            .map(|(address, _)| address)
+            .collect();
+
+        match agreed.as_slice() {
+            [address] => {
+                if votes.len() > 1 {
+                    log::warn!(
+                        "Sources disagree on the {} address, but {} is agreed by at least {} sources: {}",
+                        family,
+                        address,
+                        required_agreement,
+                        describe_votes()
+                    );
+                }
+                Ok(**address)
+            }
+            [] if votes.len() > 1 => Err(format!(
+                "Sources disagree on the {} address and none is agreed by {} sources: {}",
+                family,
+                required_agreement,
+                describe_votes()
+            )),
+            [] => Err(format!(
+                "Only {} sources returned an address while {} are required",
+                votes.first().map_or(0, |(_, voters)| voters.len()),
+                required_agreement
+            )),
+            _ => Err(format!(
+                "More than one {} address is agreed by {} sources: {}",
+                family,
+                required_agreement,
+                describe_votes()
+            )),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::ip_source::command::CommandSourceConfig;
+
+    /// A source returning `address`, or failing if it is `None`.
+    fn stub(address: Option<&str>) -> IpSource {
+        let script = match address {
+            Some(address) => format!("echo {}", address),
+            None => "exit 1".to_string(),
+        };
+        IpSource::Command(CommandSourceConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script],
+            timeout: 5,
+            extract: Default::default(),
+        })
+    }
+
+    fn fallback(
+        addresses: &[Option<&str>],
+        required_agreement: Option<usize>,
+    ) -> FallbackSourceConfig {
+        FallbackSourceConfig {
+            sources: addresses.iter().map(|address| stub(*address)).collect(),
+            required_agreement,
+        }
+    }
+
+    #[test]
+    fn first_address_without_agreement() {
+        let config = fallback(&[None, Some("198.51.100.2"), Some("198.51.100.3")], None);
+        assert_eq!(
+            config.get_address(AddressFamily::Ipv4),
+            Ok("198.51.100.2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn enough_sources_agree() {
+        let config = fallback(
+            &[
+                Some("198.51.100.1"),
+                Some("198.51.100.2"),
+                Some("198.51.100.1"),
+            ],
+            Some(2),
+        );
+        assert_eq!(
+            config.get_address(AddressFamily::Ipv4),
+            Ok("198.51.100.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn disagreeing_sources_return_nothing() {
+        let config = fallback(
+            &[
+                Some("198.51.100.1"),
+                Some("198.51.100.2"),
+                Some("198.51.100.3"),
+            ],
+            Some(2),
+        );
+        let error = config.get_address(AddressFamily::Ipv4).unwrap_err();
+        assert!(error.starts_with("Sources disagree"), "{}", error);
+    }
+
+    #[test]
+    fn too_few_sources_answer() {
+        let config = fallback(&[Some("198.51.100.1"), None, None], Some(2));
+        let error = config.get_address(AddressFamily::Ipv4).unwrap_err();
+        assert!(
+            error.starts_with("Only 1 sources returned an address"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn required_agreement_out_of_range() {
+        let addresses = [Some("198.51.100.1"), Some("198.51.100.1")];
+        for required_agreement in [0, 3] {
+            let error = fallback(&addresses, Some(required_agreement))
+                .get_address(AddressFamily::Ipv4)
+                .unwrap_err();
+            assert!(
+                error.starts_with(
+                    "required_agreement must be between 1 and the number of sources (2)"
+                ),
+                "{}",
+                error
+            );
+        }
+    }
+}
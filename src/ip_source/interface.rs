@@ -0,0 +1,191 @@
+use serde::Deserialize;
+use std::net::IpAddr;
+
+use super::AddressFamily;
+#[cfg(unix)]
+use crate::address_filter::is_global;
+
+/// Get the address by reading the addresses currently assigned to a local network interface.
+#[derive(Debug, Deserialize, Clone)]
+pub struct InterfaceSourceConfig {
+    /// The name of the interface, e.g. `eth0`.
+    pub interface: String,
+    /// Only use addresses that are globally reachable.
+    #[serde(default = "default_true")]
+    pub global_only: bool,
+    /// Skip temporary (privacy extension) IPv6 addresses.
+    #[serde(default = "default_true")]
+    pub exclude_temporary: bool,
+    /// Skip deprecated IPv6 addresses, i.e. those whose preferred lifetime has expired.
+    #[serde(default = "default_true")]
+    pub exclude_deprecated: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// An address found on the interface, together with the flags used for filtering.
+struct InterfaceAddress {
+    address: IpAddr,
+    global: bool,
+    temporary: bool,
+    deprecated: bool,
+}
+
+impl InterfaceSourceConfig {
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        log::trace!(
+            "Reading {} addresses of interface {}",
+            family,
+            self.interface
+        );
+
+        let addresses = match family {
+            AddressFamily::Ipv4 => list_ipv4_addresses(&self.interface)?,
+            AddressFamily::Ipv6 => list_ipv6_addresses(&self.interface)?,
+        };
+
+        if addresses.is_empty() {
+            return Err(format!(
+                "Interface {} has no {} address.",
+                self.interface, family
+            ));
+        }
+
+        for candidate in &addresses {
+            log::trace!(
+                "Interface {} has address {} (global: {}, temporary: {}, deprecated: {})",
+                self.interface,
+                candidate.address,
+                candidate.global,
+                candidate.temporary,
+                candidate.deprecated
+            );
+        }
+
+        match addresses.iter().find(|candidate| {
+            (!self.global_only || candidate.global)
+                && (!self.exclude_temporary || !candidate.temporary)
+                && (!self.exclude_deprecated || !candidate.deprecated)
+        }) {
+            Some(candidate) => Ok(candidate.address),
+            None => Err(format!(
+                "None of the {} addresses of interface {} passes the filters.",
+                family, self.interface
+            )),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn list_ipv4_addresses(interface: &str) -> Result<Vec<InterfaceAddress>, String> {
+    let interface_addresses = match nix::ifaddrs::getifaddrs() {
+        Ok(addresses) => addresses,
+        Err(reason) => return Err(format!("Error listing interface addresses: {}", reason)),
+    };
+
+    Ok(interface_addresses
+        .filter(|interface_address| interface_address.interface_name == interface)
+        .filter_map(|interface_address| {
+            let address = interface_address.address?.as_sockaddr_in()?.ip();
+            Some(InterfaceAddress {
+                address: address.into(),
+                global: is_global(address.into()),
+                temporary: false,
+                deprecated: false,
+            })
+        })
+        .collect())
+}
+
+// Linux exposes the flags of every IPv6 address in /proc/net/if_inet6, which getifaddrs does not tell.
+#[cfg(target_os = "linux")]
+fn list_ipv6_addresses(interface: &str) -> Result<Vec<InterfaceAddress>, String> {
+    // Values from linux/if_addr.h
+    const IFA_F_TEMPORARY: u32 = 0x01;
+    const IFA_F_DADFAILED: u32 = 0x08;
+    const IFA_F_DEPRECATED: u32 = 0x20;
+    const IFA_F_TENTATIVE: u32 = 0x40;
+    // Value from net/ipv6.h
+    const IPV6_ADDR_SCOPE_GLOBAL: u32 = 0x00;
+
+    let content = match std::fs::read_to_string("/proc/net/if_inet6") {
+        Ok(content) => content,
+        Err(reason) => return Err(format!("Error reading /proc/net/if_inet6: {}", reason)),
+    };
+
+    let mut addresses = Vec::new();
+    // Every line looks like:
+    // 20010db8000000000000000000002903 02 40 00 00 eth0
+    // address, interface index, prefix length, scope, flags, interface name
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 6 || fields[5] != interface {
+            continue;
+        }
+
+        let address = match u128::from_str_radix(fields[0], 16) {
+            Ok(address) => std::net::Ipv6Addr::from(address),
+            Err(reason) => {
+                return Err(format!(
+                    "Error parsing /proc/net/if_inet6 line {}: {}",
+                    line, reason
+                ))
+            }
+        };
+        let (scope, flags) = match (
+            u32::from_str_radix(fields[3], 16),
+            u32::from_str_radix(fields[4], 16),
+        ) {
+            (Ok(scope), Ok(flags)) => (scope, flags),
+            _ => return Err(format!("Error parsing /proc/net/if_inet6 line {}", line)),
+        };
+
+        // Addresses that are not usable yet or any more are never candidates.
+        if flags & (IFA_F_TENTATIVE | IFA_F_DADFAILED) != 0 {
+            continue;
+        }
+
+        addresses.push(InterfaceAddress {
+            address: address.into(),
+            global: scope == IPV6_ADDR_SCOPE_GLOBAL && is_global(address.into()),
+            temporary: flags & IFA_F_TEMPORARY != 0,
+            deprecated: flags & IFA_F_DEPRECATED != 0,
+        });
+    }
+
+    Ok(addresses)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn list_ipv6_addresses(interface: &str) -> Result<Vec<InterfaceAddress>, String> {
+    let interface_addresses = match nix::ifaddrs::getifaddrs() {
+        Ok(addresses) => addresses,
+        Err(reason) => return Err(format!("Error listing interface addresses: {}", reason)),
+    };
+
+    // getifaddrs does not report address flags, so temporary and deprecated addresses cannot be told apart here.
+    Ok(interface_addresses
+        .filter(|interface_address| interface_address.interface_name == interface)
+        .filter_map(|interface_address| {
+            let address = interface_address.address?.as_sockaddr_in6()?.ip();
+            Some(InterfaceAddress {
+                address: address.into(),
+                global: is_global(address.into()),
+                temporary: false,
+                deprecated: false,
+            })
+        })
+        .collect())
+}
+
+#[cfg(not(unix))]
+fn list_ipv4_addresses(_interface: &str) -> Result<Vec<InterfaceAddress>, String> {
+    Err("Reading interface addresses is not supported on this platform.".to_string())
+}
+
+#[cfg(not(unix))]
+fn list_ipv6_addresses(_interface: &str) -> Result<Vec<InterfaceAddress>, String> {
+    Err("Reading interface addresses is not supported on this platform.".to_string())
+}
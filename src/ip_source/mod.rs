@@ -0,0 +1,124 @@
+pub mod command;
+pub mod dns;
+pub mod extract;
+pub mod fallback;
+pub mod interface;
+pub mod neighbor;
+pub mod router;
+pub mod stun;
+pub mod url;
+
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The address family an ip source is asked for. A records are fed by `Ipv4` and AAAA records by `Ipv6`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    /// Parse `content` as an address of this family.
+    pub fn parse_address(&self, content: &str) -> Result<IpAddr, String> {
+        match self {
+            AddressFamily::Ipv4 => match content.parse::<Ipv4Addr>() {
+                Ok(address) => Ok(address.into()),
+                Err(_) => Err(format!(
+                    "Content cannot be parsed as an IPv4 address. Content: {}",
+                    content
+                )),
+            },
+            AddressFamily::Ipv6 => match content.parse::<Ipv6Addr>() {
+                Ok(address) => Ok(address.into()),
+                Err(_) => Err(format!(
+                    "Content cannot be parsed as an IPv6 address. Content: {}",
+                    content
+                )),
+            },
+        }
+    }
+}
+
+impl From<IpAddr> for AddressFamily {
+    fn from(value: IpAddr) -> Self {
+        match value {
+            IpAddr::V4(_) => AddressFamily::Ipv4,
+            IpAddr::V6(_) => AddressFamily::Ipv6,
+        }
+    }
+}
+
+impl std::fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressFamily::Ipv4 => write!(f, "IPv4"),
+            AddressFamily::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// A enum representing all supported methods of acquiring the public address of current machine and their
+/// settings. Should be deserialized from config file.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IpSource {
+    Url(url::UrlSourceConfig),
+    Interface(interface::InterfaceSourceConfig),
+    Fallback(fallback::FallbackSourceConfig),
+    Dns(dns::DnsSourceConfig),
+    Stun(stun::StunSourceConfig),
+    Router(router::RouterSourceConfig),
+    Command(command::CommandSourceConfig),
+    Neighbor(neighbor::NeighborSourceConfig),
+}
+
+impl IpSource {
+    /// Acquire the address of `family` from this source. If failed, return a string specify the reason.
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        match self {
+            IpSource::Url(config) => config.get_address(family),
+            IpSource::Interface(config) => config.get_address(family),
+            IpSource::Fallback(config) => config.get_address(family),
+            IpSource::Dns(config) => config.get_address(family),
+            IpSource::Stun(config) => config.get_address(family),
+            IpSource::Router(config) => config.get_address(family),
+            IpSource::Command(config) => config.get_address(family),
+            IpSource::Neighbor(config) => config.get_address(family),
+        }
+    }
+}
+
+impl std::fmt::Display for IpSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpSource::Url(config) => write!(f, "url {}", config.url),
+            IpSource::Interface(config) => write!(f, "interface {}", config.interface),
+            IpSource::Fallback(config) => write!(f, "fallback of {} sources", config.sources.len()),
+            IpSource::Dns(config) => match (&config.name, config.preset) {
+                (Some(name), _) => write!(f, "dns {}", name),
+                (None, Some(preset)) => write!(f, "dns preset {:?}", preset),
+                (None, None) => write!(f, "dns"),
+            },
+            IpSource::Stun(config) => write!(f, "stun {}", config.servers.join(", ")),
+            IpSource::Router(config) => write!(f, "router via {}", config.protocol),
+            IpSource::Command(config) => write!(f, "command {}", config.command),
+            IpSource::Neighbor(config) => write!(f, "neighbor {}", config.mac_address),
+        }
+    }
+}
+
+/// An id telling the answer to a request over UDP apart from stray datagrams and the answers to other clients, e.g.
+/// a STUN transaction id or a PCP nonce. It does not need to be unpredictable, so it is made of the time and the
+/// process id.
+fn request_id() -> [u8; 12] {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    let mut id = [0u8; 12];
+    id[..8].copy_from_slice(&(nanos as u64).to_be_bytes());
+    id[8..].copy_from_slice(&std::process::id().to_be_bytes());
+    id
+}
@@ -0,0 +1,382 @@
+use serde::Deserialize;
+use std::net::IpAddr;
+
+use super::AddressFamily;
+use crate::address;
+use crate::address_filter::is_global;
+
+/// Get the address currently held by a device on the LAN by looking its MAC address up in the kernel neighbor
+/// table. This lets one instance running on the router publish records for other devices, even when they use
+/// random (privacy or stable-privacy) interface ids.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NeighborSourceConfig {
+    /// The MAC address of the device, e.g. `52:54:00:12:34:56`.
+    pub mac_address: String,
+    /// Only look at the neighbors on this interface, e.g. `br-lan`. Required by `probe`.
+    pub interface: Option<String>,
+    /// Only use addresses that are globally reachable.
+    #[serde(default = "default_true")]
+    pub global_only: bool,
+    /// Before the lookup, ping all nodes on `interface` from a global address, so that devices that have been
+    /// quiet show up in the table. Only IPv6 is probed.
+    #[serde(default)]
+    pub probe: bool,
+    /// Seconds to wait for the replies to a probe.
+    #[serde(default = "default_probe_wait")]
+    pub probe_wait: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_probe_wait() -> u64 {
+    1
+}
+
+/// An entry of the neighbor table.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Neighbor {
+    address: IpAddr,
+    mac_address: [u8; 6],
+    interface_index: u32,
+    state: u16,
+}
+
+// Values from linux/neighbour.h
+const NUD_INCOMPLETE: u16 = 0x01;
+const NUD_REACHABLE: u16 = 0x02;
+const NUD_STALE: u16 = 0x04;
+const NUD_FAILED: u16 = 0x20;
+const NUD_PERMANENT: u16 = 0x80;
+
+impl NeighborSourceConfig {
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        let mac_address = address::parse_mac_address(&self.mac_address)?;
+        let interface_index = match &self.interface {
+            Some(interface) => Some(interface_index(interface)?),
+            None => None,
+        };
+
+        if self.probe {
+            match (family, &self.interface) {
+                (AddressFamily::Ipv6, Some(interface)) => {
+                    if let Err(reason) = probe(interface, self.probe_wait) {
+                        log::warn!(
+                            "Probing neighbors on {} failed, looking up the neighbor table anyway: {}",
+                            interface,
+                            reason
+                        );
+                    }
+                }
+                (AddressFamily::Ipv6, None) => {
+                    log::warn!("Probing neighbors needs an interface. Skipping the probe.")
+                }
+                (AddressFamily::Ipv4, _) => {
+                    log::debug!("Only IPv6 neighbors are probed. Skipping the probe.")
+                }
+            }
+        }
+
+        log::trace!(
+            "Looking up {} neighbors with MAC address {}",
+            family,
+            self.mac_address
+        );
+        let mut candidates: Vec<Neighbor> = list_neighbors(family)?
+            .into_iter()
+            .filter(|neighbor| neighbor.mac_address == mac_address)
+            .filter(|neighbor| {
+                interface_index.is_none_or(|index| neighbor.interface_index == index)
+            })
+            .filter(|neighbor| neighbor.state & (NUD_INCOMPLETE | NUD_FAILED) == 0)
+            .collect();
+
+        for candidate in &candidates {
+            log::trace!(
+                "Neighbor {} has address {} (state: {:#x})",
+                self.mac_address,
+                candidate.address,
+                candidate.state
+            );
+        }
+
+        candidates.retain(|neighbor| !self.global_only || is_global(neighbor.address));
+        // Prefer the addresses that are confirmed to be in use over the ones that may be gone.
+        candidates.sort_by_key(|neighbor| match neighbor.state {
+            state if state & (NUD_REACHABLE | NUD_PERMANENT) != 0 => 0,
+            state if state & NUD_STALE == 0 => 1,
+            _ => 2,
+        });
+        if candidates.len() > 1 {
+            log::debug!(
+                "Neighbor {} has {} usable {} addresses, using {}",
+                self.mac_address,
+                candidates.len(),
+                family,
+                candidates[0].address
+            );
+        }
+
+        match candidates.first() {
+            Some(neighbor) => Ok(neighbor.address),
+            None => Err(format!(
+                "No usable {} address of {} is found in the neighbor table",
+                family, self.mac_address
+            )),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn interface_index(interface: &str) -> Result<u32, String> {
+    match nix::net::if_::if_nametoindex(interface) {
+        Ok(index) => Ok(index),
+        Err(reason) => Err(format!("Interface {} is not found: {}", interface, reason)),
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_interface: &str) -> Result<u32, String> {
+    Err("Reading the neighbor table is not supported on this platform".to_string())
+}
+
+/// Dump the neighbor table of `family` with a netlink RTM_GETNEIGH request.
+#[cfg(target_os = "linux")]
+fn list_neighbors(family: AddressFamily) -> Result<Vec<Neighbor>, String> {
+    use nix::sys::socket::{
+        bind, recv, send, setsockopt, socket, sockopt, AddressFamily as SocketFamily, MsgFlags,
+        NetlinkAddr, SockFlag, SockProtocol, SockType,
+    };
+    use nix::sys::time::TimeVal;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::os::fd::AsRawFd;
+
+    // Values from linux/socket.h, linux/netlink.h and linux/rtnetlink.h
+    const AF_INET: u8 = 2;
+    const AF_INET6: u8 = 10;
+    const NLMSG_ERROR: u16 = 2;
+    const NLMSG_DONE: u16 = 3;
+    const RTM_NEWNEIGH: u16 = 28;
+    const RTM_GETNEIGH: u16 = 30;
+    const NLM_F_REQUEST: u16 = 0x01;
+    const NLM_F_DUMP: u16 = 0x300;
+    const NDA_DST: u16 = 1;
+    const NDA_LLADDR: u16 = 2;
+    const NLMSG_HEADER_LENGTH: usize = 16;
+    const NDMSG_LENGTH: usize = 12;
+
+    let socket_family: u8 = match family {
+        AddressFamily::Ipv4 => AF_INET,
+        AddressFamily::Ipv6 => AF_INET6,
+    };
+    let align = |length: usize| (length + 3) & !3;
+
+    let fd = match socket(
+        SocketFamily::Netlink,
+        SockType::Raw,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::NetlinkRoute,
+    ) {
+        Ok(fd) => fd,
+        Err(reason) => return Err(format!("Error opening netlink socket: {}", reason)),
+    };
+    let result = bind(fd.as_raw_fd(), &NetlinkAddr::new(0, 0))
+        .and_then(|_| setsockopt(&fd, sockopt::ReceiveTimeout, &TimeVal::new(5, 0)));
+    if let Err(reason) = result {
+        return Err(format!("Error setting up netlink socket: {}", reason));
+    }
+
+    // struct nlmsghdr followed by struct ndmsg, whose fields other than the family are left 0.
+    let mut request = Vec::with_capacity(NLMSG_HEADER_LENGTH + NDMSG_LENGTH);
+    request.extend_from_slice(&((NLMSG_HEADER_LENGTH + NDMSG_LENGTH) as u32).to_ne_bytes());
+    request.extend_from_slice(&RTM_GETNEIGH.to_ne_bytes());
+    request.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    request.extend_from_slice(&1u32.to_ne_bytes());
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    request.push(socket_family);
+    request.resize(NLMSG_HEADER_LENGTH + NDMSG_LENGTH, 0);
+    if let Err(reason) = send(fd.as_raw_fd(), &request, MsgFlags::empty()) {
+        return Err(format!("Error sending netlink request: {}", reason));
+    }
+
+    let u16_at =
+        |buffer: &[u8], offset: usize| u16::from_ne_bytes([buffer[offset], buffer[offset + 1]]);
+    let u32_at = |buffer: &[u8], offset: usize| {
+        u32::from_ne_bytes([
+            buffer[offset],
+            buffer[offset + 1],
+            buffer[offset + 2],
+            buffer[offset + 3],
+        ])
+    };
+
+    let mut neighbors = Vec::new();
+    let mut buffer = vec![0u8; 65536];
+    loop {
+        let length = match recv(fd.as_raw_fd(), &mut buffer, MsgFlags::empty()) {
+            Ok(length) => length,
+            Err(reason) => return Err(format!("Error receiving netlink response: {}", reason)),
+        };
+
+        let mut offset = 0;
+        while offset + NLMSG_HEADER_LENGTH <= length {
+            let message_length = u32_at(&buffer, offset) as usize;
+            if message_length < NLMSG_HEADER_LENGTH || offset + message_length > length {
+                return Err("Netlink response is malformed".to_string());
+            }
+            let message = &buffer[offset..offset + message_length];
+            offset += align(message_length);
+
+            match u16_at(message, 4) {
+                NLMSG_DONE => return Ok(neighbors),
+                NLMSG_ERROR => {
+                    // struct nlmsgerr starts with the error code after the header.
+                    if message_length < NLMSG_HEADER_LENGTH + 4 {
+                        return Err("Netlink error message is truncated".to_string());
+                    }
+                    let error = u32_at(message, NLMSG_HEADER_LENGTH) as i32;
+                    if error != 0 {
+                        return Err(format!(
+                            "Netlink request failed: {}",
+                            std::io::Error::from_raw_os_error(-error)
+                        ));
+                    }
+                    continue;
+                }
+                RTM_NEWNEIGH if message_length >= NLMSG_HEADER_LENGTH + NDMSG_LENGTH => {}
+                _ => continue,
+            }
+
+            let interface_index = u32_at(message, NLMSG_HEADER_LENGTH + 4);
+            let state = u16_at(message, NLMSG_HEADER_LENGTH + 8);
+            let mut address = None;
+            let mut mac_address = None;
+
+            // The rest of the message is a list of struct rtattr.
+            let mut attribute_offset = NLMSG_HEADER_LENGTH + NDMSG_LENGTH;
+            while attribute_offset + 4 <= message_length {
+                let attribute_length = u16_at(message, attribute_offset) as usize;
+                if attribute_length < 4 || attribute_offset + attribute_length > message_length {
+                    break;
+                }
+                let payload = &message[attribute_offset + 4..attribute_offset + attribute_length];
+                match (u16_at(message, attribute_offset + 2), payload.len()) {
+                    (NDA_DST, 4) => {
+                        address = Some(IpAddr::from(Ipv4Addr::from(
+                            <[u8; 4]>::try_from(payload).expect("Length is checked"),
+                        )))
+                    }
+                    (NDA_DST, 16) => {
+                        address = Some(IpAddr::from(Ipv6Addr::from(
+                            <[u8; 16]>::try_from(payload).expect("Length is checked"),
+                        )))
+                    }
+                    (NDA_LLADDR, 6) => {
+                        mac_address = Some(<[u8; 6]>::try_from(payload).expect("Length is checked"))
+                    }
+                    _ => {}
+                }
+                attribute_offset += align(attribute_length);
+            }
+
+            if let (Some(address), Some(mac_address)) = (address, mac_address) {
+                neighbors.push(Neighbor {
+                    address,
+                    mac_address,
+                    interface_index,
+                    state,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn list_neighbors(_family: AddressFamily) -> Result<Vec<Neighbor>, String> {
+    Err("Reading the neighbor table is not supported on this platform".to_string())
+}
+
+/// Send an ICMPv6 echo request to all nodes on `interface` from a global address of it, then wait `wait` seconds.
+/// Devices answer from their global addresses, which puts these addresses into the neighbor table.
+#[cfg(target_os = "linux")]
+fn probe(interface: &str, wait: u64) -> Result<(), String> {
+    use nix::sys::socket::{
+        bind, sendto, socket, AddressFamily as SocketFamily, MsgFlags, SockFlag, SockProtocol,
+        SockType, SockaddrIn6,
+    };
+    use std::net::{Ipv6Addr, SocketAddrV6};
+    use std::os::fd::AsRawFd;
+
+    // ICMPv6 echo request type, from RFC 4443
+    const ICMPV6_ECHO_REQUEST: u8 = 128;
+    const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+    let interface_index = interface_index(interface)?;
+    let source = match nix::ifaddrs::getifaddrs() {
+        Ok(addresses) => addresses
+            .filter(|interface_address| interface_address.interface_name == interface)
+            .filter_map(|interface_address| {
+                Some(interface_address.address?.as_sockaddr_in6()?.ip())
+            })
+            .find(|address| is_global((*address).into())),
+        Err(reason) => return Err(format!("Error listing interface addresses: {}", reason)),
+    };
+    let source = match source {
+        Some(source) => source,
+        None => {
+            return Err(format!(
+                "Interface {} has no global IPv6 address",
+                interface
+            ))
+        }
+    };
+
+    // Unprivileged ping sockets are tried first, raw sockets need CAP_NET_RAW.
+    let fd = match socket(
+        SocketFamily::Inet6,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::IcmpV6,
+    )
+    .or_else(|_| {
+        socket(
+            SocketFamily::Inet6,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            SockProtocol::IcmpV6,
+        )
+    }) {
+        Ok(fd) => fd,
+        Err(reason) => return Err(format!("Error opening ICMPv6 socket: {}", reason)),
+    };
+    if let Err(reason) = bind(
+        fd.as_raw_fd(),
+        &SockaddrIn6::from(SocketAddrV6::new(source, 0, 0, 0)),
+    ) {
+        return Err(format!(
+            "Error binding ICMPv6 socket to {}: {}",
+            source, reason
+        ));
+    }
+
+    // Type, code, checksum (filled by the kernel), identifier and sequence number.
+    let request = [ICMPV6_ECHO_REQUEST, 0, 0, 0, 0, 1, 0, 1];
+    let destination = SockaddrIn6::from(SocketAddrV6::new(ALL_NODES, 0, 0, interface_index));
+    if let Err(reason) = sendto(fd.as_raw_fd(), &request, &destination, MsgFlags::empty()) {
+        return Err(format!("Error sending ICMPv6 echo request: {}", reason));
+    }
+    log::trace!(
+        "Sent ICMPv6 echo request from {} to all nodes on {}",
+        source,
+        interface
+    );
+
+    std::thread::sleep(std::time::Duration::from_secs(wait));
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn probe(_interface: &str, _wait: u64) -> Result<(), String> {
+    Err("Probing neighbors is not supported on this platform".to_string())
+}
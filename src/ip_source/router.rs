@@ -0,0 +1,434 @@
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use super::{request_id, AddressFamily};
+use crate::address_filter::{special_range, SHARED_ADDRESS_SPACE};
+
+const NAT_PMP_PORT: u16 = 5351;
+const SSDP_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+const WAN_CONNECTION_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// Get the address by asking the router for the address of its WAN interface. Only IPv4 is supported.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RouterSourceConfig {
+    pub protocol: RouterProtocol,
+    /// The address of the router. Defaults to the default gateway in the routing table, which is only read on
+    /// Linux.
+    pub gateway: Option<Ipv4Addr>,
+    /// Only for UPnP. The url of the device description of the router, which skips the SSDP discovery.
+    pub description_url: Option<String>,
+    /// Seconds to wait for the router.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RouterProtocol {
+    /// `GetExternalIPAddress` of the UPnP Internet Gateway Device.
+    Upnp,
+    /// RFC 6886 NAT Port Mapping Protocol.
+    Natpmp,
+    /// RFC 6887 Port Control Protocol.
+    Pcp,
+}
+
+impl std::fmt::Display for RouterProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouterProtocol::Upnp => write!(f, "UPnP"),
+            RouterProtocol::Natpmp => write!(f, "NAT-PMP"),
+            RouterProtocol::Pcp => write!(f, "PCP"),
+        }
+    }
+}
+
+impl RouterSourceConfig {
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        if family != AddressFamily::Ipv4 {
+            return Err(format!(
+                "Router source only supports IPv4, {} is requested",
+                family
+            ));
+        }
+
+        let timeout = Duration::from_secs(self.timeout);
+        let address = match self.protocol {
+            RouterProtocol::Upnp => self.get_address_upnp(timeout)?,
+            RouterProtocol::Natpmp => get_address_nat_pmp(self.gateway()?, timeout)?,
+            RouterProtocol::Pcp => get_address_pcp(self.gateway()?, timeout)?,
+        };
+        log::debug!(
+            "Router reported WAN address {} via {}",
+            address,
+            self.protocol
+        );
+
+        if SHARED_ADDRESS_SPACE.contains(&address) {
+            log::warn!(
+                "The WAN address {} of the router is in carrier-grade NAT range {}. The router is behind another NAT, e.g. the one of the ISP, so the address is not reachable from the internet.",
+                address,
+                SHARED_ADDRESS_SPACE
+            );
+        } else if let Some((range, description)) = special_range(address.into()) {
+            log::warn!(
+                "The WAN address {} of the router is in {} range {}, which is not publicly routable, so the address is not reachable from the internet.",
+                address,
+                description,
+                range
+            );
+        }
+
+        Ok(address.into())
+    }
+
+    fn gateway(&self) -> Result<Ipv4Addr, String> {
+        match self.gateway {
+            Some(gateway) => Ok(gateway),
+            None => default_gateway(),
+        }
+    }
+
+    fn get_address_upnp(&self, timeout: Duration) -> Result<Ipv4Addr, String> {
+        let description_url = match &self.description_url {
+            Some(url) => url.clone(),
+            None => discover_upnp_gateway(self.gateway, timeout)?,
+        };
+        log::trace!("UPnP device description url: {}", description_url);
+
+        let client = match reqwest::blocking::Client::builder()
+            .timeout(timeout)
+            .build()
+        {
+            Ok(client) => client,
+            Err(reason) => return Err(format!("Error creating http client: {}", reason)),
+        };
+
+        let description = match client.get(&description_url).send() {
+            Ok(response) => match response.text() {
+                Ok(text) => text,
+                Err(reason) => {
+                    return Err(format!("Error decoding device description: {}", reason))
+                }
+            },
+            Err(reason) => return Err(format!("Error fetching device description: {}", reason)),
+        };
+        log::trace!("UPnP device description: {}", description);
+
+        // Find the first WAN connection service and its control url.
+        let (service_type, control_url) =
+            match description.split("<service>").skip(1).find_map(|service| {
+                let service_type = xml_element(service, "serviceType")?;
+                if !WAN_CONNECTION_SERVICES.contains(&service_type) {
+                    return None;
+                }
+                Some((service_type, xml_element(service, "controlURL")?))
+            }) {
+                Some(service) => service,
+                None => return Err("The router has no WAN connection service".to_string()),
+            };
+
+        let base_url = xml_element(&description, "URLBase").unwrap_or(&description_url);
+        let control_url = match reqwest::Url::parse(base_url)
+            .and_then(|base| base.join(control_url))
+        {
+            Ok(url) => url,
+            Err(reason) => return Err(format!("Invalid control url {}: {}", control_url, reason)),
+        };
+        log::trace!("UPnP {} control url: {}", service_type, control_url);
+
+        let body = format!(
+            concat!(
+                r#"<?xml version="1.0"?>"#,
+                r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+                r#"<s:Body><u:GetExternalIPAddress xmlns:u="{}"></u:GetExternalIPAddress></s:Body>"#,
+                r#"</s:Envelope>"#
+            ),
+            service_type
+        );
+        let response = match client
+            .post(control_url)
+            .header("Content-Type", r#"text/xml; charset="utf-8""#)
+            .header(
+                "SOAPAction",
+                format!(r#""{}#GetExternalIPAddress""#, service_type),
+            )
+            .body(body)
+            .send()
+        {
+            Ok(response) => match response.text() {
+                Ok(text) => text,
+                Err(reason) => return Err(format!("Error decoding soap response: {}", reason)),
+            },
+            Err(reason) => return Err(format!("Error sending soap request: {}", reason)),
+        };
+        log::trace!("UPnP GetExternalIPAddress returned: {}", response);
+
+        match xml_element(&response, "NewExternalIPAddress") {
+            Some(address) => match address.trim().parse::<Ipv4Addr>() {
+                Ok(address) => Ok(address),
+                Err(_) => Err(format!("Router returned an invalid address: {}", address)),
+            },
+            None => Err(format!("Router did not return an address: {}", response)),
+        }
+    }
+}
+
+/// Return the text between `<tag>` and `</tag>`. Good enough for the flat documents routers return.
+fn xml_element<'a>(document: &'a str, tag: &str) -> Option<&'a str> {
+    let start = document.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + document[start..].find(&format!("</{}>", tag))?;
+    Some(document[start..end].trim())
+}
+
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Result<Ipv4Addr, String> {
+    const RTF_GATEWAY: u32 = 0x0002;
+
+    let content = match std::fs::read_to_string("/proc/net/route") {
+        Ok(content) => content,
+        Err(reason) => return Err(format!("Error reading /proc/net/route: {}", reason)),
+    };
+
+    // Every line after the header looks like:
+    // eth0	00000000	0101A8C0	0003	0	0	0	00000000	0	0	0
+    // interface, destination, gateway, flags, ...
+    // The addresses are hex of the in memory representation, so they are in native byte order.
+    for line in content.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 || fields[1] != "00000000" {
+            continue;
+        }
+        if let (Ok(gateway), Ok(flags)) = (
+            u32::from_str_radix(fields[2], 16),
+            u32::from_str_radix(fields[3], 16),
+        ) {
+            if flags & RTF_GATEWAY != 0 {
+                let gateway = Ipv4Addr::from(gateway.to_ne_bytes());
+                log::trace!("Default gateway is {} on {}", gateway, fields[0]);
+                return Ok(gateway);
+            }
+        }
+    }
+
+    Err("No default gateway found in /proc/net/route".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> Result<Ipv4Addr, String> {
+    Err(
+        "Finding the default gateway is only supported on Linux, not on this platform. Set gateway for natpmp and pcp"
+            .to_string(),
+    )
+}
+
+/// Find the device description url of the internet gateway device with SSDP. If `gateway` is provided, only
+/// its answer is accepted.
+fn discover_upnp_gateway(gateway: Option<Ipv4Addr>, timeout: Duration) -> Result<String, String> {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) {
+        Ok(socket) => socket,
+        Err(reason) => return Err(format!("Error binding udp socket: {}", reason)),
+    };
+    let request = concat!(
+        "M-SEARCH * HTTP/1.1\r\n",
+        "HOST: 239.255.255.250:1900\r\n",
+        "MAN: \"ssdp:discover\"\r\n",
+        "MX: 2\r\n",
+        "ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n",
+        "\r\n"
+    );
+    if let Err(reason) = socket.send_to(request.as_bytes(), SSDP_ADDRESS) {
+        return Err(format!("Error sending SSDP search: {}", reason));
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0u8; 2048];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("No internet gateway device answered the SSDP search".to_string());
+        }
+        if let Err(reason) = socket.set_read_timeout(Some(remaining)) {
+            return Err(format!("Error setting socket timeout: {}", reason));
+        }
+        let (length, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(reason)
+                if matches!(
+                    reason.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(reason) => return Err(format!("Error receiving SSDP answer: {}", reason)),
+        };
+        if gateway.is_some_and(|gateway| from.ip() != IpAddr::from(gateway)) {
+            log::trace!("Ignoring SSDP answer from {}", from);
+            continue;
+        }
+        let answer = String::from_utf8_lossy(&buffer[..length]);
+        log::trace!("SSDP answer from {}: {}", from, answer);
+        let location = answer.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("location")
+                .then(|| value.trim().to_string())
+        });
+        if let Some(location) = location {
+            return Ok(location);
+        }
+    }
+}
+
+/// Send `request` to the router and wait for an answer accepted by `is_answer`, retrying with the doubling
+/// interval from RFC 6886 until `timeout`.
+fn exchange(
+    socket: &UdpSocket,
+    request: &[u8],
+    timeout: Duration,
+    is_answer: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>, String> {
+    let deadline = Instant::now() + timeout;
+    let mut interval = Duration::from_millis(250);
+    let mut buffer = [0u8; 1100];
+    loop {
+        if let Err(reason) = socket.send(request) {
+            return Err(format!("Error sending request: {}", reason));
+        }
+        let resend_at = Instant::now() + interval;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(format!(
+                    "No answer from the router within {} seconds",
+                    timeout.as_secs()
+                ));
+            }
+            if now >= resend_at {
+                break;
+            }
+            if let Err(reason) = socket.set_read_timeout(Some(resend_at.min(deadline) - now)) {
+                return Err(format!("Error setting socket timeout: {}", reason));
+            }
+            match socket.recv(&mut buffer) {
+                Ok(length) if is_answer(&buffer[..length]) => return Ok(buffer[..length].to_vec()),
+                Ok(_) => log::trace!("Ignoring unrelated datagram from the router"),
+                Err(reason)
+                    if matches!(
+                        reason.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) => {}
+                Err(reason) => return Err(format!("Error receiving answer: {}", reason)),
+            }
+        }
+        interval *= 2;
+    }
+}
+
+fn connect_router(gateway: Ipv4Addr) -> Result<UdpSocket, String> {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) {
+        Ok(socket) => socket,
+        Err(reason) => return Err(format!("Error binding udp socket: {}", reason)),
+    };
+    if let Err(reason) = socket.connect((gateway, NAT_PMP_PORT)) {
+        return Err(format!("Error connecting to {}: {}", gateway, reason));
+    }
+    Ok(socket)
+}
+
+fn get_address_nat_pmp(gateway: Ipv4Addr, timeout: Duration) -> Result<Ipv4Addr, String> {
+    log::trace!("Sending NAT-PMP external address request to {}", gateway);
+    let socket = connect_router(gateway)?;
+
+    // Version 0, opcode 0: external address request.
+    let answer = exchange(&socket, &[0, 0], timeout, |answer| {
+        answer.len() >= 2 && answer[0] == 0 && answer[1] == 128
+    })?;
+    // Version, opcode, result code, seconds since start of epoch, external address.
+    if answer.len() < 12 {
+        return Err("NAT-PMP answer is truncated".to_string());
+    }
+    let result_code = u16::from_be_bytes([answer[2], answer[3]]);
+    if result_code != 0 {
+        return Err(format!("NAT-PMP result code {}", result_code));
+    }
+    Ok(Ipv4Addr::new(answer[8], answer[9], answer[10], answer[11]))
+}
+
+fn get_address_pcp(gateway: Ipv4Addr, timeout: Duration) -> Result<Ipv4Addr, String> {
+    const PCP_VERSION: u8 = 2;
+    const OPCODE_MAP: u8 = 1;
+    const PROTOCOL_UDP: u8 = 17;
+    // PCP has no request for only the external address, so a short lived mapping of our own socket is requested
+    // and deleted afterwards.
+    const LIFETIME: u32 = 120;
+
+    log::trace!("Sending PCP map request to {}", gateway);
+    let socket = connect_router(gateway)?;
+    let local_address = match socket.local_addr() {
+        Ok(SocketAddr::V4(address)) => address,
+        Ok(address) => return Err(format!("Unexpected local address {}", address)),
+        Err(reason) => return Err(format!("Error reading local address: {}", reason)),
+    };
+
+    let nonce = request_id();
+    let map_request = |lifetime: u32| {
+        let mut request = Vec::with_capacity(60);
+        request.extend_from_slice(&[PCP_VERSION, OPCODE_MAP, 0, 0]);
+        request.extend_from_slice(&lifetime.to_be_bytes());
+        request.extend_from_slice(&local_address.ip().to_ipv6_mapped().octets());
+        request.extend_from_slice(&nonce);
+        request.extend_from_slice(&[PROTOCOL_UDP, 0, 0, 0]);
+        // Internal port and suggested external port.
+        request.extend_from_slice(&local_address.port().to_be_bytes());
+        request.extend_from_slice(&local_address.port().to_be_bytes());
+        // No suggested external address.
+        request.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+        request
+    };
+
+    let answer = exchange(&socket, &map_request(LIFETIME), timeout, |answer| {
+        // A NAT-PMP only router answers with version 0.
+        answer.len() >= 2 && (answer[0] == 0 || answer[1] == 0x80 | OPCODE_MAP)
+    })?;
+    if answer[0] != PCP_VERSION {
+        return Err(format!(
+            "Router does not support PCP, answered with version {}",
+            answer[0]
+        ));
+    }
+    if answer.len() < 60 {
+        return Err("PCP answer is truncated".to_string());
+    }
+    let result_code = answer[3];
+    if result_code != 0 {
+        return Err(format!("PCP result code {}", result_code));
+    }
+    if answer[24..36] != nonce {
+        return Err("PCP answer nonce does not match the request".to_string());
+    }
+
+    // Best effort, the mapping expires anyway.
+    if let Err(reason) = socket.send(&map_request(0)) {
+        log::debug!("Deleting PCP mapping failed: {}", reason);
+    }
+
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(&answer[44..60]);
+    match Ipv6Addr::from(octets).to_ipv4_mapped() {
+        Some(address) => Ok(address),
+        None => Err(format!(
+            "PCP returned a non IPv4 address {}",
+            Ipv6Addr::from(octets)
+        )),
+    }
+}
@@ -0,0 +1,317 @@
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use super::{request_id, AddressFamily};
+
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+const ATTRIBUTE_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTRIBUTE_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Get the address by sending a STUN (RFC 5389) binding request to a STUN server, which answers with the address
+/// the request came from after all the NATs on the way.
+#[derive(Debug, Deserialize, Clone)]
+pub struct StunSourceConfig {
+    /// STUN servers tried in order, e.g. `stun.l.google.com:19302`. The port is 3478 if omitted. Only the
+    /// addresses of the server of the family being acquired are used.
+    pub servers: Vec<String>,
+    /// Seconds to wait for every server.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+impl StunSourceConfig {
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        if self.servers.is_empty() {
+            return Err("No STUN server is provided".to_string());
+        }
+
+        for server in &self.servers {
+            let server_address = match resolve_server(server, family) {
+                Ok(address) => address,
+                Err(reason) => {
+                    log::warn!("Skipping STUN server {}: {}", server, reason);
+                    continue;
+                }
+            };
+            match self.query(server_address, family) {
+                Ok(address) => return Ok(address),
+                Err(reason) => log::warn!("STUN server {} failed: {}", server, reason),
+            }
+        }
+
+        Err(format!("All {} STUN servers failed", self.servers.len()))
+    }
+
+    fn query(&self, server: SocketAddr, family: AddressFamily) -> Result<IpAddr, String> {
+        log::trace!("Sending STUN binding request to {}", server);
+
+        let transaction_id = request_id();
+        let mut request = Vec::with_capacity(20);
+        request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+        // No attributes.
+        request.extend_from_slice(&0u16.to_be_bytes());
+        request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        request.extend_from_slice(&transaction_id);
+
+        let bind_address: SocketAddr = match family {
+            AddressFamily::Ipv4 => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            AddressFamily::Ipv6 => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = match UdpSocket::bind(bind_address) {
+            Ok(socket) => socket,
+            Err(reason) => return Err(format!("Error binding udp socket: {}", reason)),
+        };
+        if let Err(reason) = socket.set_read_timeout(Some(Duration::from_secs(self.timeout))) {
+            return Err(format!("Error setting socket timeout: {}", reason));
+        }
+        if let Err(reason) = socket.send_to(&request, server) {
+            return Err(format!("Error sending binding request: {}", reason));
+        }
+
+        let mut buffer = [0u8; 1500];
+        loop {
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(reason)
+                    if matches!(
+                        reason.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(format!("No response within {} seconds", self.timeout))
+                }
+                Err(reason) => return Err(format!("Error receiving response: {}", reason)),
+            };
+            let response = &buffer[..length];
+            // Ignore anything that is not the response to our request.
+            if from != server || response.len() < 20 || response[8..20] != transaction_id {
+                log::trace!("Ignoring unrelated datagram from {}", from);
+                continue;
+            }
+            return parse_response(response, &transaction_id, family);
+        }
+    }
+}
+
+fn resolve_server(server: &str, family: AddressFamily) -> Result<SocketAddr, String> {
+    let addresses = if let Ok(address) = server.parse::<IpAddr>() {
+        vec![SocketAddr::new(address, 3478)]
+    } else if let Ok(address) = server.parse::<SocketAddr>() {
+        vec![address]
+    } else {
+        let resolved = if server.contains(':') {
+            server.to_socket_addrs()
+        } else {
+            (server, 3478).to_socket_addrs()
+        };
+        match resolved {
+            Ok(addresses) => addresses.collect(),
+            Err(reason) => return Err(format!("Error resolving address: {}", reason)),
+        }
+    };
+
+    match addresses
+        .into_iter()
+        .find(|address| AddressFamily::from(address.ip()) == family)
+    {
+        Some(address) => Ok(address),
+        None => Err(format!("The server has no {} address", family)),
+    }
+}
+
+fn parse_response(
+    message: &[u8],
+    transaction_id: &[u8; 12],
+    family: AddressFamily,
+) -> Result<IpAddr, String> {
+    let message_type = u16::from_be_bytes([message[0], message[1]]);
+    let message_length = u16::from_be_bytes([message[2], message[3]]) as usize;
+    match message_type {
+        BINDING_SUCCESS_RESPONSE => {}
+        BINDING_ERROR_RESPONSE => return Err("Server returned an error response".to_string()),
+        other => return Err(format!("Unexpected message type {:#06x}", other)),
+    }
+    let attributes = match message.get(20..20 + message_length) {
+        Some(attributes) => attributes,
+        None => return Err("Response is truncated".to_string()),
+    };
+
+    let mut mapped_address = None;
+    let mut position = 0;
+    while position + 4 <= attributes.len() {
+        let attribute_type = u16::from_be_bytes([attributes[position], attributes[position + 1]]);
+        let attribute_length =
+            u16::from_be_bytes([attributes[position + 2], attributes[position + 3]]) as usize;
+        let value = match attributes.get(position + 4..position + 4 + attribute_length) {
+            Some(value) => value,
+            None => return Err("Response attribute is truncated".to_string()),
+        };
+        // Attributes are padded to a multiple of 4 bytes.
+        position += 4 + attribute_length.div_ceil(4) * 4;
+
+        match attribute_type {
+            ATTRIBUTE_XOR_MAPPED_ADDRESS => {
+                let address = parse_address_attribute(value, Some(transaction_id))?;
+                log::trace!("XOR-MAPPED-ADDRESS: {}", address);
+                return check_family(address, family);
+            }
+            // Only used by servers that predate RFC 5389 and do not send XOR-MAPPED-ADDRESS.
+            ATTRIBUTE_MAPPED_ADDRESS => {
+                mapped_address = Some(parse_address_attribute(value, None)?);
+            }
+            _ => {}
+        }
+    }
+
+    match mapped_address {
+        Some(address) => {
+            log::trace!("MAPPED-ADDRESS: {}", address);
+            check_family(address, family)
+        }
+        None => Err("Response has no mapped address".to_string()),
+    }
+}
+
+/// Parse a (XOR-)MAPPED-ADDRESS value. The address is xored when `transaction_id` is provided.
+fn parse_address_attribute(
+    value: &[u8],
+    transaction_id: Option<&[u8; 12]>,
+) -> Result<IpAddr, String> {
+    // Reserved byte, family, port, then the address.
+    let mut mask = [0u8; 16];
+    if let Some(transaction_id) = transaction_id {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+    }
+    match (value.get(1), value.len()) {
+        (Some(0x01), 8) => {
+            let mut octets = [0u8; 4];
+            for (index, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + index] ^ mask[index];
+            }
+            Ok(Ipv4Addr::from(octets).into())
+        }
+        (Some(0x02), 20) => {
+            let mut octets = [0u8; 16];
+            for (index, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + index] ^ mask[index];
+            }
+            Ok(Ipv6Addr::from(octets).into())
+        }
+        _ => Err("Malformed mapped address attribute".to_string()),
+    }
+}
+
+fn check_family(address: IpAddr, family: AddressFamily) -> Result<IpAddr, String> {
+    if AddressFamily::from(address) == family {
+        Ok(address)
+    } else {
+        Err(format!(
+            "Mapped address {} is not an {} address",
+            address, family
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSACTION_ID: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+    /// A binding success response carrying the attribute `attribute_type` with `value`.
+    fn response(attribute_type: u16, value: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&BINDING_SUCCESS_RESPONSE.to_be_bytes());
+        message.extend_from_slice(&(4 + value.len() as u16).to_be_bytes());
+        message.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        message.extend_from_slice(&TRANSACTION_ID);
+        message.extend_from_slice(&attribute_type.to_be_bytes());
+        message.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        message.extend_from_slice(value);
+        message
+    }
+
+    /// The XOR-MAPPED-ADDRESS value of `address` on port 3478, as built by a server.
+    fn xor_mapped_address(address: IpAddr) -> Vec<u8> {
+        let mut mask = MAGIC_COOKIE.to_be_bytes().to_vec();
+        mask.extend_from_slice(&TRANSACTION_ID);
+        let (family, octets) = match address {
+            IpAddr::V4(address) => (1, address.octets().to_vec()),
+            IpAddr::V6(address) => (2, address.octets().to_vec()),
+        };
+        let mut value = vec![0, family];
+        value.extend_from_slice(&(3478 ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+        value.extend(octets.iter().zip(&mask).map(|(octet, mask)| octet ^ mask));
+        value
+    }
+
+    #[test]
+    fn parse_xor_mapped_ipv4() {
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        let message = response(ATTRIBUTE_XOR_MAPPED_ADDRESS, &xor_mapped_address(address));
+        assert_eq!(
+            parse_response(&message, &TRANSACTION_ID, AddressFamily::Ipv4),
+            Ok(address)
+        );
+    }
+
+    #[test]
+    fn parse_xor_mapped_ipv6() {
+        let address: IpAddr = "2001:db8::1".parse().unwrap();
+        let message = response(ATTRIBUTE_XOR_MAPPED_ADDRESS, &xor_mapped_address(address));
+        assert_eq!(
+            parse_response(&message, &TRANSACTION_ID, AddressFamily::Ipv6),
+            Ok(address)
+        );
+        assert!(parse_response(&message, &TRANSACTION_ID, AddressFamily::Ipv4).is_err());
+    }
+
+    #[test]
+    fn parse_mapped_address() {
+        let message = response(ATTRIBUTE_MAPPED_ADDRESS, &[0, 1, 0x0d, 0x96, 192, 0, 2, 1]);
+        assert_eq!(
+            parse_response(&message, &TRANSACTION_ID, AddressFamily::Ipv4),
+            Ok("192.0.2.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_truncated_response() {
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        let message = response(ATTRIBUTE_XOR_MAPPED_ADDRESS, &xor_mapped_address(address));
+        // The message is shorter than its length field.
+        assert_eq!(
+            parse_response(
+                &message[..message.len() - 1],
+                &TRANSACTION_ID,
+                AddressFamily::Ipv4
+            ),
+            Err("Response is truncated".to_string())
+        );
+        // The attribute is longer than the message.
+        let mut message = message;
+        message[22..24].copy_from_slice(&12u16.to_be_bytes());
+        assert_eq!(
+            parse_response(&message, &TRANSACTION_ID, AddressFamily::Ipv4),
+            Err("Response attribute is truncated".to_string())
+        );
+        // The address is cut short.
+        let message = response(
+            ATTRIBUTE_XOR_MAPPED_ADDRESS,
+            &xor_mapped_address(address)[..6],
+        );
+        assert_eq!(
+            parse_response(&message, &TRANSACTION_ID, AddressFamily::Ipv4),
+            Err("Malformed mapped address attribute".to_string())
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use super::{extract::ExtractRule, AddressFamily};
+
+/// Get the address by sending a GET request to `url` and reading the address from the response with `extract`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct UrlSourceConfig {
+    pub url: String,
+    /// Seconds to wait for the response before giving up on this url.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default)]
+    pub extract: ExtractRule,
+}
+
+pub fn default_timeout() -> u64 {
+    10
+}
+
+impl UrlSourceConfig {
+    pub fn get_address(&self, family: AddressFamily) -> Result<IpAddr, String> {
+        log::trace!("Requesting {} address from {}", family, self.url);
+
+        let client = match reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(self.timeout))
+            .build()
+        {
+            Ok(client) => client,
+            Err(reason) => return Err(format!("Error creating http client: {}", reason)),
+        };
+
+        let response = match client.get(&self.url).send() {
+            Ok(response) => response,
+            Err(reason) => {
+                return Err(format!("Error sending request to {}: {}", self.url, reason))
+            }
+        };
+
+        let content = match response.text() {
+            Ok(content) => content,
+            Err(reason) => return Err(format!("Error decoding response: {}", reason)),
+        };
+
+        log::trace!("{} returned text: {}", self.url, content);
+
+        self.extract.extract(&content, family)
+    }
+}
@@ -2,19 +2,29 @@
 // mod config_parser;
 // mod domain_record_changer;
 
+mod address;
+mod address_filter;
+mod address_mapping;
 mod api;
 mod config;
 mod domain_record_changer;
+mod ip_source;
+mod state;
+mod template;
 
-use api::cloudflare::CloudflareInterface;
+use api::cloudflare::{AuthorizeCache, CloudflareInterface};
 use api::{ApiInterface, ServiceProvider};
 use chrono::Utc;
 use clap::Parser;
-use domain_record_changer::DomainRecordChanger;
+use domain_record_changer::{DomainRecordChanger, ReverseZone};
+use ip_source::AddressFamily;
 use log::LevelFilter;
+use state::State;
+use std::collections::HashMap;
 use std::fs;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::IpAddr;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use log4rs::{
@@ -140,101 +150,127 @@ fn main() {
 
     log::debug!("Deserialized settings object: {:?}", settings);
 
-    let ipv4_address = thread::spawn(|| {
-        let result = match reqwest::blocking::get(settings.get_ip_urls.ipv4) {
-            Ok(content) => content,
-            Err(reason) => {
-                log::error!(
-                    "Get IPv4 address failed: {}. Will not update any A record.",
-                    reason
-                );
-                return Err(());
-            }
-        };
-        let result = match result.text() {
-            Ok(content) => content,
-            Err(reason) => {
-                log::error!(
-                    "Get IPv4 address failed: {}. Will not update any A record.",
-                    reason
-                );
-                return Err(());
+    let state_file_path = args.state_file.as_deref().map(Path::new);
+    let state = match state_file_path {
+        Some(path) => State::load(path).unwrap_or_else(|reason| {
+            log::error!("{}. Starting with an empty state.", reason);
+            State::default()
+        }),
+        None => {
+            if settings
+                .domain_settings
+                .iter()
+                .filter_map(|d| d.on_address_loss.as_ref())
+                .any(|policy| policy.after_runs > 1)
+            {
+                log::warn!("on_address_loss needs --state-file to remember the runs without an address. Without it, only after_runs = 1 takes effect.");
             }
-        };
-        Ok(result)
-    });
+            State::default()
+        }
+    };
+    let state = Mutex::new(state);
 
-    let ipv6_address = thread::spawn(|| {
-        let result = match reqwest::blocking::get(settings.get_ip_urls.ipv6) {
-            Ok(content) => content,
-            Err(reason) => {
-                log::error!(
-                    "Get IPv6 address failed: {}. Will not update any AAAA record.",
-                    reason
+    let mut ip_sources = settings.ip_sources;
+    if let Some(get_ip_urls) = settings.get_ip_urls {
+        for (name, source) in get_ip_urls.into_ip_sources() {
+            if ip_sources.contains_key(&name) {
+                log::warn!(
+                    "Ip source {} is defined in both ip_sources and get_ip_urls. The one in get_ip_urls is ignored.",
+                    name
                 );
-                return Err(());
+                continue;
             }
-        };
-        let result = match result.text() {
-            Ok(content) => content,
-            Err(reason) => {
-                log::error!(
-                    "Get IPv6 address failed: {}. Will not update any AAAA record.",
-                    reason
-                );
-                return Err(());
-            }
-        };
-        Ok(result)
-    });
-
-    let ipv4_address = ipv4_address.join().unwrap_or_else(|_| {
-        log::error!("Get ipv4 address failed: thread exited abnormally.");
-        Err(())
-    });
-
-    let ipv6_address = ipv6_address.join().unwrap_or_else(|_| {
-        log::error!("Get ipv6 address failed: thread exited abnormally.");
-        Err(())
-    });
+            ip_sources.insert(name, source);
+        }
+    }
 
-    let ipv4_address = match ipv4_address {
-        Ok(content) => {
-            let content_ip = content.trim().parse::<Ipv4Addr>();
-            match content_ip {
-                Ok(address) => {
-                    log::info!("Got ipv4 addres: {}", address.to_string());
-                    Ok(address)
-                }
-                Err(_) => {
-                    log::error!(
-                        "Content returned from the ipv4 api cannot be parsed as an IPv4 address. Content: {}", content
-                    );
-                    Err(())
+    // Every ip source is asked once for every address family it is used for by an enabled domain.
+    let mut needed_sources: Vec<(String, AddressFamily)> = Vec::new();
+    for single_domain_settings in settings.domain_settings.iter().filter(|d| d.enabled) {
+        for record_type in single_domain_settings.record_type.iter() {
+            for family in single_domain_settings.address_families(record_type) {
+                for subdomain_settings in single_domain_settings
+                    .subdomains
+                    .iter()
+                    .filter(|s| s.address.is_none() && s.manages(record_type))
+                {
+                    let names = if subdomain_settings.publishes_set(record_type) {
+                        subdomain_settings
+                            .ip_sources
+                            .iter()
+                            .map(String::as_str)
+                            .collect()
+                    } else {
+                        vec![subdomain_settings
+                            .ip_source
+                            .as_deref()
+                            .unwrap_or(single_domain_settings.ip_source_name(family))]
+                    };
+                    for name in names {
+                        if !needed_sources
+                            .iter()
+                            .any(|(n, f)| n == name && *f == family)
+                        {
+                            needed_sources.push((name.to_string(), family));
+                        }
+                    }
                 }
             }
         }
-        Err(_) => Err(()),
-    };
+    }
 
-    let ipv6_address = match ipv6_address {
-        Ok(content) => {
-            let content_ip = content.trim().parse::<Ipv6Addr>();
-            match content_ip {
-                Ok(address) => {
-                    log::info!("Got ipv6 address: {}", address.to_string());
-                    Ok(address)
-                }
-                Err(_) => {
-                    log::error!(
-                        "Content returned from the ipv6 api cannot be parsed as an IPv4 address. Content: {}", content
-                    );
-                    Err(())
+    let acquisitions: Vec<_> = needed_sources
+        .into_iter()
+        .map(|(name, family)| {
+            let source = ip_sources.get(&name).cloned();
+            let thread_name = name.clone();
+            let handle = thread::spawn(move || {
+                let source = match source {
+                    Some(source) => source,
+                    None => {
+                        log::error!("Ip source {} is not defined in ip_sources.", thread_name);
+                        return Err(());
+                    }
+                };
+                match source.get_address(family) {
+                    Ok(address) => {
+                        log::info!(
+                            "Got {} address from ip source {}: {}",
+                            family,
+                            thread_name,
+                            address
+                        );
+                        Ok(address)
+                    }
+                    Err(reason) => {
+                        log::error!(
+                            "Get {} address from ip source {} failed: {}. Will not update any record using it.",
+                            family,
+                            thread_name,
+                            reason
+                        );
+                        Err(())
+                    }
                 }
-            }
+            });
+            ((name, family), handle)
+        })
+        .collect();
+
+    let mut addresses: HashMap<(String, AddressFamily), IpAddr> = HashMap::new();
+    for ((name, family), handle) in acquisitions {
+        let address = handle.join().unwrap_or_else(|_| {
+            log::error!(
+                "Get {} address from ip source {} failed: thread exited abnormally.",
+                family,
+                name
+            );
+            Err(())
+        });
+        if let Ok(address) = address {
+            addresses.insert((name, family), address);
         }
-        Err(_) => Err(()),
-    };
+    }
 
     let pool = rayon::ThreadPoolBuilder::new()
         .num_threads(args.thread_number.into())
@@ -244,29 +280,53 @@ fn main() {
             panic!("Create thread pool failed: {}", reason)
         });
 
+    // Every api token and zone is only checked once in the run.
+    let authorize_cache = Arc::new(AuthorizeCache::default());
     pool.scope(|s| {
         for single_domain_settings in settings.domain_settings {
-            let current_ip_address: IpAddr = match single_domain_settings.record_type {
-                RecordType::A => match &ipv4_address {
-                    Ok(address) => IpAddr::V4(*address),
-                    Err(_) => {
-                        log::error!("Skipping A record update for {} as a result of previously failed ip address aquisition.", single_domain_settings.domain_name);
-                        continue;
-                    }
-                },
-                RecordType::Aaaa => match &ipv6_address {
-                    Ok(address) => IpAddr::V6(*address),
-                    Err(_) => {
-                        log::error!("Skipping AAAA record update for {} as a result of previously failed ip address aquisition.", single_domain_settings.domain_name);
-                        continue;
-                    }
-                },
-            };
-            let mut changer = match single_domain_settings.service_provider.clone() {
-                ServiceProvider::Cloudflare(build_config) => DomainRecordChanger::new(single_domain_settings, current_ip_address, CloudflareInterface::new(build_config))
-            };
-            s.spawn(move |_| {changer.start_ddns();});
+            // A dual-stack domain is run once for every record type, so that a failed family only skips itself.
+            for record_type in single_domain_settings.record_type.iter() {
+                let families = single_domain_settings.address_families(record_type);
+                let domain_addresses: HashMap<(String, AddressFamily), IpAddr> = addresses
+                    .iter()
+                    .filter(|((_, f), _)| families.contains(f))
+                    .map(|(key, address)| (key.clone(), *address))
+                    .collect();
+                if single_domain_settings.enabled
+                    && !families.is_empty()
+                    && domain_addresses.is_empty()
+                    && single_domain_settings.on_address_loss.is_none()
+                    && single_domain_settings
+                        .subdomains
+                        .iter()
+                        .filter(|s| s.manages(record_type))
+                        .all(|s| s.address.is_none())
+                {
+                    log::error!("Skipping {} record update for {} as a result of previously failed ip address aquisition.", record_type, single_domain_settings.domain_name);
+                    continue;
+                }
+                let reverse_zones = single_domain_settings
+                    .reverse_zones
+                    .iter()
+                    .map(|zone| ReverseZone {
+                        name: zone.name.trim_end_matches('.').to_ascii_lowercase(),
+                        api_interface: match zone.service_provider.clone() {
+                            ServiceProvider::Cloudflare(build_config) => CloudflareInterface::new(build_config).with_authorize_cache(authorize_cache.clone()),
+                        },
+                    })
+                    .collect();
+                let mut changer = match single_domain_settings.service_provider.clone() {
+                    ServiceProvider::Cloudflare(build_config) => DomainRecordChanger::new(single_domain_settings.clone(), record_type, domain_addresses, CloudflareInterface::new(build_config).with_authorize_cache(authorize_cache.clone()), reverse_zones)
+                };
+                let state = &state;
+                s.spawn(move |_| {changer.start_ddns(state);});
+            }
         }
     });
+    if let Some(path) = state_file_path {
+        if let Err(reason) = state::lock(&state).save(path) {
+            log::error!("{}", reason);
+        }
+    }
     log::info!("DDNS script ended.");
 }
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::RecordType;
+
+/// What is remembered between runs. Saved as json in the file given by `--state-file`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// The number of consecutive runs in which the address of a record could not be acquired, by record type and
+    /// full domain name, e.g. `AAAA www.example.com`.
+    #[serde(default)]
+    missing_address_runs: HashMap<String, u32>,
+    /// The full domain name that the PTR record of an address was last found or made to point at, e.g. `192.0.2.1`
+    /// to `www.example.com`.
+    #[serde(default)]
+    synced_ptrs: HashMap<String, String>,
+}
+
+impl State {
+    /// Read the state from `path`. A file that does not exist yet is an empty state.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(reason) if reason.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(State::default())
+            }
+            Err(reason) => return Err(format!("Cannot read state file: {}", reason)),
+        };
+        match serde_json::from_str(&content) {
+            Ok(state) => Ok(state),
+            Err(reason) => Err(format!("State file parse error: {}", reason)),
+        }
+    }
+
+    /// Write the state into `path`, creating the parent folders if needed.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            if let Err(reason) = fs::create_dir_all(parent) {
+                return Err(format!("Cannot create folder for state file: {}", reason));
+            }
+        }
+        let content = match serde_json::to_string_pretty(self) {
+            Ok(content) => content,
+            Err(reason) => return Err(format!("Cannot serialize state: {}", reason)),
+        };
+        match fs::write(path, content) {
+            Ok(_) => Ok(()),
+            Err(reason) => Err(format!("Cannot write state file: {}", reason)),
+        }
+    }
+
+    /// Count one more run without an address for the record, and return the number of consecutive such runs.
+    pub fn add_missing_address_run(
+        &mut self,
+        record_type: RecordType,
+        full_domain_name: &str,
+    ) -> u32 {
+        let runs = self
+            .missing_address_runs
+            .entry(format!("{} {}", record_type, full_domain_name))
+            .or_insert(0);
+        *runs += 1;
+        *runs
+    }
+
+    /// Forget the runs without an address for the record, as its address is acquired again.
+    pub fn clear_missing_address_runs(&mut self, record_type: RecordType, full_domain_name: &str) {
+        self.missing_address_runs
+            .remove(&format!("{} {}", record_type, full_domain_name));
+    }
+
+    /// Whether the PTR record of `address` is known to point at `full_domain_name`.
+    pub fn ptr_synced(&self, address: IpAddr, full_domain_name: &str) -> bool {
+        self.synced_ptrs
+            .get(&address.to_string())
+            .is_some_and(|name| name == full_domain_name)
+    }
+
+    /// Remember that the PTR record of `address` points at `full_domain_name`.
+    pub fn set_ptr_synced(&mut self, address: IpAddr, full_domain_name: &str) {
+        self.synced_ptrs
+            .insert(address.to_string(), full_domain_name.to_string());
+    }
+
+    /// Forget the PTR record of `address`, as it is deleted or no longer checked.
+    pub fn clear_ptr_synced(&mut self, address: IpAddr) {
+        self.synced_ptrs.remove(&address.to_string());
+    }
+}
+
+/// Lock data shared between threads, e.g. the state or the results of authorizing the api. Every change to the data
+/// is complete once made, so a thread panicking while holding the lock does not leave it inconsistent, and the lock
+/// is still used when poisoned.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,280 @@
+use chrono::Utc;
+use serde::Deserialize;
+use std::net::IpAddr;
+
+use crate::ip_source::AddressFamily;
+
+/// A text with placeholders like `{ipv4}` filled in for every record. `{{` and `}}` stand for literal braces.
+/// Should be deserialized from a string in the config file.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct Template(Vec<Part>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    /// The IPv4 address of the subdomain.
+    Ipv4,
+    /// The IPv6 address of the subdomain.
+    Ipv6,
+    /// The full domain name of the subdomain.
+    Name,
+    /// The host name of the machine running the script.
+    Hostname,
+    /// The time the content is written, in RFC 3339 UTC.
+    Timestamp,
+    /// The version of the script.
+    Version,
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut characters = value.chars();
+        while let Some(character) = characters.next() {
+            match character {
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match characters.next() {
+                            Some('{') if name.is_empty() => {
+                                text.push('{');
+                                break;
+                            }
+                            Some('}') => {
+                                let placeholder = match name.as_str() {
+                                    "ipv4" => Placeholder::Ipv4,
+                                    "ipv6" => Placeholder::Ipv6,
+                                    "name" => Placeholder::Name,
+                                    "hostname" => Placeholder::Hostname,
+                                    "timestamp" => Placeholder::Timestamp,
+                                    "version" => Placeholder::Version,
+                                    _ => return Err(format!("Unknown placeholder {{{}}}", name)),
+                                };
+                                if !text.is_empty() {
+                                    parts.push(Part::Text(std::mem::take(&mut text)));
+                                }
+                                parts.push(Part::Placeholder(placeholder));
+                                break;
+                            }
+                            Some(character) => name.push(character),
+                            None => return Err(format!("Unclosed placeholder {{{}", name)),
+                        }
+                    }
+                }
+                '}' => match characters.next() {
+                    Some('}') => text.push('}'),
+                    _ => return Err("A single } should be written as }}".to_string()),
+                },
+                _ => text.push(character),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template(parts))
+    }
+}
+
+/// The values filled into the placeholders of a [`Template`].
+pub struct TemplateValues {
+    pub ipv4: Option<IpAddr>,
+    pub ipv6: Option<IpAddr>,
+    pub name: String,
+    pub hostname: String,
+    pub timestamp: String,
+}
+
+impl TemplateValues {
+    /// Values for the subdomain `name`, without addresses, written now.
+    pub fn new(name: &str) -> Self {
+        TemplateValues {
+            ipv4: None,
+            ipv6: None,
+            name: name.to_string(),
+            hostname: hostname(),
+            timestamp: Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        }
+    }
+
+    /// Set the address of the family of `address`.
+    pub fn set_address(&mut self, address: IpAddr) {
+        match AddressFamily::from(address) {
+            AddressFamily::Ipv4 => self.ipv4 = Some(address),
+            AddressFamily::Ipv6 => self.ipv6 = Some(address),
+        }
+    }
+}
+
+impl Template {
+    /// The address families whose addresses are used by the template.
+    pub fn address_families(&self) -> Vec<AddressFamily> {
+        let mut families = Vec::new();
+        for part in &self.0 {
+            let family = match part {
+                Part::Placeholder(Placeholder::Ipv4) => AddressFamily::Ipv4,
+                Part::Placeholder(Placeholder::Ipv6) => AddressFamily::Ipv6,
+                _ => continue,
+            };
+            if !families.contains(&family) {
+                families.push(family);
+            }
+        }
+        families
+    }
+
+    /// Fill `values` into the template. A missing address is left empty.
+    pub fn render(&self, values: &TemplateValues) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Placeholder(placeholder) => Self::value(*placeholder, values),
+            })
+            .collect()
+    }
+
+    /// Return whether `content` is the template rendered with `values` at any time, i.e. whether it only differs
+    /// from a freshly rendered one by the timestamp. Used to keep the time of the last change in the record.
+    pub fn matches(&self, content: &str, values: &TemplateValues) -> bool {
+        let pattern: String = self
+            .0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => regex::escape(text),
+                Part::Placeholder(Placeholder::Timestamp) => ".*".to_string(),
+                Part::Placeholder(placeholder) => regex::escape(&Self::value(*placeholder, values)),
+            })
+            .collect();
+        match regex::Regex::new(&format!("^{}$", pattern)) {
+            Ok(pattern) => pattern.is_match(content),
+            Err(reason) => {
+                log::error!("Build pattern of template failed: {}", reason);
+                false
+            }
+        }
+    }
+
+    fn value(placeholder: Placeholder, values: &TemplateValues) -> String {
+        let address = |address: Option<IpAddr>| address.map(|a| a.to_string()).unwrap_or_default();
+        match placeholder {
+            Placeholder::Ipv4 => address(values.ipv4),
+            Placeholder::Ipv6 => address(values.ipv6),
+            Placeholder::Name => values.name.clone(),
+            Placeholder::Hostname => values.hostname.clone(),
+            Placeholder::Timestamp => values.timestamp.clone(),
+            Placeholder::Version => env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// The host name of the machine running the script, or an empty string if it is unknown.
+#[cfg(unix)]
+fn hostname() -> String {
+    match nix::unistd::gethostname() {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(reason) => {
+            log::warn!("Get host name failed: {}", reason);
+            String::new()
+        }
+    }
+}
+
+/// The host name of the machine running the script, or an empty string if it is unknown.
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(text: &str) -> Result<Template, String> {
+        Template::try_from(text.to_string())
+    }
+
+    fn values(timestamp: &str) -> TemplateValues {
+        TemplateValues {
+            ipv4: Some("198.51.100.7".parse().unwrap()),
+            ipv6: None,
+            name: "www.example.com".to_string(),
+            hostname: "router".to_string(),
+            timestamp: timestamp.to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let template =
+            template("ip={ipv4} v6={ipv6} name={name} on {hostname} at {timestamp}").unwrap();
+        assert_eq!(
+            template.render(&values("2024-01-01T00:00:00Z")),
+            "ip=198.51.100.7 v6= name=www.example.com on router at 2024-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            template.address_families(),
+            [AddressFamily::Ipv4, AddressFamily::Ipv6]
+        );
+    }
+
+    #[test]
+    fn renders_version() {
+        assert_eq!(
+            template("ddns {version}").unwrap().render(&values("")),
+            format!("ddns {}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() {
+        let template = template("{{\"ip\": \"{ipv4}\"}} {{name}}").unwrap();
+        assert_eq!(
+            template.render(&values("")),
+            "{\"ip\": \"198.51.100.7\"} {name}"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert_eq!(
+            template("ip={address}").unwrap_err(),
+            "Unknown placeholder {address}"
+        );
+        assert_eq!(
+            template("ip={ipv4").unwrap_err(),
+            "Unclosed placeholder {ipv4"
+        );
+        assert_eq!(
+            template("ip=ipv4}").unwrap_err(),
+            "A single } should be written as }}"
+        );
+    }
+
+    #[test]
+    fn matches_when_only_the_timestamp_differs() {
+        let template = template("ip={ipv4} (at {timestamp}) [{name}]").unwrap();
+        let content = template.render(&values("2024-01-01T00:00:00Z"));
+
+        assert!(template.matches(&content, &values("2024-06-30T12:00:00Z")));
+        let mut moved = values("2024-06-30T12:00:00Z");
+        moved.ipv4 = Some("198.51.100.8".parse().unwrap());
+        assert!(!template.matches(&content, &moved));
+        assert!(!template.matches(&format!("{} ", content), &values("")));
+    }
+
+    #[test]
+    fn matches_without_timestamp_only_the_same_content() {
+        let template = template("ip={ipv4}.*").unwrap();
+        assert!(template.matches("ip=198.51.100.7.*", &values("")));
+        assert!(!template.matches("ip=198.51.100.7 and more", &values("")));
+    }
+}
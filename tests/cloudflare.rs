@@ -0,0 +1,560 @@
+mod common;
+
+use common::{FakeCloudflare, API_TOKEN};
+
+/// The config of one domain managing the A record of `www.example.com`, after the ip sources of `server`.
+fn config(server: &FakeCloudflare, api_token: &str, create_new_record: bool) -> String {
+    format!(
+        "{}[[domain_settings]]
+enabled = true
+domain_name = \"example.com\"
+service_provider = {}
+record_type = \"A\"
+create_new_record = {}
+allowed_ranges = [\"198.51.100.0/24\"]
+subdomains = [ {{ name = \"www\" }} ]
+",
+        server.ip_sources(),
+        server.service_provider(api_token),
+        create_new_record
+    )
+}
+
+fn assert_succeeded(output: &std::process::Output) {
+    assert!(
+        output.status.success(),
+        "run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn creates_missing_record() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+
+    let output = common::run("creates_missing_record", &config(&server, API_TOKEN, true));
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [("www.example.com".into(), "A".into(), "198.51.100.7".into())]
+    );
+    assert_eq!(
+        server
+            .requests("GET")
+            .iter()
+            .filter(|r| *r == "GET /zones")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn does_not_create_record_when_disabled() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+
+    let output = common::run(
+        "does_not_create_record_when_disabled",
+        &config(&server, API_TOKEN, false),
+    );
+
+    assert_succeeded(&output);
+    assert!(server.records().is_empty());
+    assert!(server.requests("POST").is_empty());
+}
+
+#[test]
+fn updates_stale_record() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+    let id = server.add_record("www.example.com", "A", "198.51.100.1");
+
+    let output = common::run("updates_stale_record", &config(&server, API_TOKEN, true));
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [("www.example.com".into(), "A".into(), "198.51.100.7".into())]
+    );
+    assert_eq!(
+        server.requests("PUT"),
+        [format!("PUT /zones/{}/dns_records/{}", common::ZONE_ID, id)]
+    );
+    assert!(server.requests("POST").is_empty());
+}
+
+#[test]
+fn leaves_current_record_alone() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+    server.add_record("www.example.com", "A", "198.51.100.7");
+
+    let output = common::run(
+        "leaves_current_record_alone",
+        &config(&server, API_TOKEN, true),
+    );
+
+    assert_succeeded(&output);
+    assert!(server.requests("PUT").is_empty());
+    assert!(server.requests("POST").is_empty());
+    assert!(server.requests("DELETE").is_empty());
+}
+
+#[test]
+fn publishes_txt_record() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+
+    let output = common::run(
+        "publishes_txt_record",
+        &config(&server, API_TOKEN, true)
+            .replace("record_type = \"A\"", "record_type = [\"A\", \"TXT\"]")
+            .replace(
+                "subdomains = [",
+                "txt_record = { content = \"ip={ipv4} name={name}\" }\nsubdomains = [",
+            ),
+    );
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [
+            (
+                "_ddns.www.example.com".into(),
+                "TXT".into(),
+                "ip=198.51.100.7 name=www.example.com".into()
+            ),
+            ("www.example.com".into(), "A".into(), "198.51.100.7".into()),
+        ]
+    );
+}
+
+#[test]
+fn rejected_token_changes_nothing() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+    server.add_record("www.example.com", "A", "198.51.100.1");
+
+    let output = common::run(
+        "rejected_token_changes_nothing",
+        &config(&server, "revoked-api-token", true),
+    );
+
+    assert_succeeded(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid API Token"));
+    assert_eq!(
+        server.records(),
+        [("www.example.com".into(), "A".into(), "198.51.100.1".into())]
+    );
+    assert!(server.requests("PUT").is_empty());
+    assert!(server.requests("POST").is_empty());
+}
+
+/// The config of `config`, with a comment and tags on the records of `www.example.com`.
+fn labelled_config(server: &FakeCloudflare) -> String {
+    config(server, API_TOKEN, true).replace(
+        "{ name = \"www\" }",
+        "{ name = \"www\", comment = \"ddns {version} {timestamp}\", tags = [\"ddns\"] }",
+    )
+}
+
+#[test]
+fn labels_created_record() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+
+    let output = common::run("labels_created_record", &labelled_config(&server));
+
+    assert_succeeded(&output);
+    let labels = server.labels("www.example.com");
+    assert_eq!(labels.len(), 1);
+    let (comment, tags) = &labels[0];
+    assert!(comment.as_deref().is_some_and(
+        |comment| comment.starts_with(&format!("ddns {} ", env!("CARGO_PKG_VERSION")))
+    ));
+    assert_eq!(tags, &["ddns"]);
+}
+
+#[test]
+fn updates_drifted_labels() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+    let id = server.add_record("www.example.com", "A", "198.51.100.7");
+    server.label_record(&id, "edited by hand", &[]);
+
+    let output = common::run("updates_drifted_labels", &labelled_config(&server));
+
+    assert_succeeded(&output);
+    assert_eq!(server.requests("PUT").len(), 1);
+    let (comment, tags) = &server.labels("www.example.com")[0];
+    assert_ne!(comment.as_deref(), Some("edited by hand"));
+    assert_eq!(tags, &["ddns"]);
+}
+
+#[test]
+fn keeps_labels_only_differing_by_timestamp() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+    let id = server.add_record("www.example.com", "A", "198.51.100.7");
+    let comment = format!("ddns {} 2024-01-01T00:00:00Z", env!("CARGO_PKG_VERSION"));
+    server.label_record(&id, &comment, &["ddns"]);
+
+    let output = common::run(
+        "keeps_labels_only_differing_by_timestamp",
+        &labelled_config(&server),
+    );
+
+    assert_succeeded(&output);
+    assert!(server.requests("PUT").is_empty());
+    assert_eq!(
+        server.labels("www.example.com"),
+        [(Some(comment), vec!["ddns".to_string()])]
+    );
+}
+
+/// The config of `config`, with the PTR records of `www.example.com` kept in the reverse zone of the fake server.
+fn reverse_zone_config(server: &FakeCloudflare) -> String {
+    config(server, API_TOKEN, true).replace(
+        "subdomains = [",
+        &format!(
+            "reverse_zones = [ {{ name = \"{}\", service_provider = {} }} ]\nsubdomains = [",
+            common::REVERSE_ZONE_NAME,
+            server.service_provider(API_TOKEN)
+        ),
+    )
+}
+
+/// The requests reading the records of the reverse zone.
+fn reverse_zone_reads(server: &FakeCloudflare) -> usize {
+    server
+        .requests("GET")
+        .iter()
+        .filter(|r| **r == format!("GET /zones/{}/dns_records", common::REVERSE_ZONE_ID))
+        .count()
+}
+
+#[test]
+fn checks_ptr_of_current_record_once() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+    server.add_record("www.example.com", "A", "198.51.100.7");
+    let state_file = std::env::temp_dir().join(format!(
+        "cloudflare-ddns-rust-{}-checks_ptr_of_current_record_once.json",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&state_file);
+    let args = ["--state-file", state_file.to_str().unwrap()];
+
+    let output = common::run_with_args(
+        "checks_ptr_of_current_record_once",
+        &reverse_zone_config(&server),
+        &args,
+    );
+    assert_succeeded(&output);
+    assert_eq!(reverse_zone_reads(&server), 1);
+    let output = common::run_with_args(
+        "checks_ptr_of_current_record_once",
+        &reverse_zone_config(&server),
+        &args,
+    );
+    let _ = std::fs::remove_file(&state_file);
+
+    assert_succeeded(&output);
+    assert_eq!(reverse_zone_reads(&server), 1);
+    assert_eq!(
+        server.records(),
+        [
+            (
+                "7.100.51.198.in-addr.arpa".into(),
+                "PTR".into(),
+                "www.example.com".into()
+            ),
+            ("www.example.com".into(), "A".into(), "198.51.100.7".into()),
+        ]
+    );
+}
+
+#[test]
+fn writes_shared_ptr_once() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+    server.add_record("example.com", "A", "198.51.100.7");
+    server.add_record("www.example.com", "A", "198.51.100.7");
+    let state_file = std::env::temp_dir().join(format!(
+        "cloudflare-ddns-rust-{}-writes_shared_ptr_once.json",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&state_file);
+    let config = reverse_zone_config(&server).replace(
+        "subdomains = [ { name = \"www\" } ]",
+        "subdomains = [ { name = \"@\" }, { name = \"www\" } ]",
+    );
+    let args = ["--state-file", state_file.to_str().unwrap()];
+
+    for _ in 0..3 {
+        let output = common::run_with_args("writes_shared_ptr_once", &config, &args);
+        assert_succeeded(&output);
+    }
+    let _ = std::fs::remove_file(&state_file);
+
+    // The first subdomain publishing the address keeps its PTR record.
+    let ptr_writes = ["POST", "PUT"]
+        .iter()
+        .flat_map(|method| server.requests(method))
+        .filter(|r| r.contains(common::REVERSE_ZONE_ID))
+        .count();
+    assert_eq!(ptr_writes, 1);
+    assert!(server.records().contains(&(
+        "7.100.51.198.in-addr.arpa".into(),
+        "PTR".into(),
+        "example.com".into()
+    )));
+}
+
+#[test]
+fn deletes_ptr_on_address_loss() {
+    let server = FakeCloudflare::start();
+    server.set_address("not an address");
+    server.add_record("www.example.com", "A", "198.51.100.1");
+    server.add_record("1.100.51.198.in-addr.arpa", "PTR", "www.example.com");
+
+    let output = common::run(
+        "deletes_ptr_on_address_loss",
+        &reverse_zone_config(&server).replace(
+            "subdomains = [",
+            "on_address_loss = { action = \"delete\", after_runs = 1 }\nsubdomains = [",
+        ),
+    );
+
+    assert_succeeded(&output);
+    assert!(server.records().is_empty());
+}
+
+#[test]
+fn verifies_token_once_per_run() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+
+    let output = common::run("verifies_token_once_per_run", &reverse_zone_config(&server));
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server
+            .requests("GET")
+            .iter()
+            .filter(|r| *r == "GET /user/tokens/verify")
+            .count(),
+        1
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("WARN The api token ending in oken is not allowed to read its own policies"));
+}
+
+/// The config of `config`, with `policy` as the address loss policy of the domain.
+fn address_loss_config(server: &FakeCloudflare, policy: &str) -> String {
+    config(server, API_TOKEN, true).replace(
+        "subdomains = [",
+        &format!("on_address_loss = {}\nsubdomains = [", policy),
+    )
+}
+
+#[test]
+fn deletes_record_on_address_loss() {
+    let server = FakeCloudflare::start();
+    server.set_address("not an address");
+    server.add_record("www.example.com", "A", "198.51.100.1");
+
+    let output = common::run(
+        "deletes_record_on_address_loss",
+        &address_loss_config(&server, "{ action = \"delete\", after_runs = 1 }"),
+    );
+
+    assert_succeeded(&output);
+    assert!(server.records().is_empty());
+}
+
+#[test]
+fn parks_record_on_address_loss() {
+    let server = FakeCloudflare::start();
+    server.set_address("not an address");
+    server.add_record("www.example.com", "A", "198.51.100.1");
+
+    let output = common::run(
+        "parks_record_on_address_loss",
+        &address_loss_config(
+            &server,
+            "{ action = \"park\", address = [\"192.0.2.80\", \"2001:db8::80\"], after_runs = 1 }",
+        ),
+    );
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [("www.example.com".into(), "A".into(), "192.0.2.80".into())]
+    );
+    assert!(server.requests("DELETE").is_empty());
+}
+
+#[test]
+fn handles_address_loss_after_runs() {
+    let server = FakeCloudflare::start();
+    server.set_address("not an address");
+    server.add_record("www.example.com", "A", "198.51.100.1");
+    let state_file = std::env::temp_dir().join(format!(
+        "cloudflare-ddns-rust-{}-handles_address_loss_after_runs.json",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&state_file);
+    let config = address_loss_config(&server, "{ action = \"delete\", after_runs = 2 }");
+    let args = ["--state-file", state_file.to_str().unwrap()];
+
+    let output = common::run_with_args("handles_address_loss_after_runs", &config, &args);
+    assert_succeeded(&output);
+    assert_eq!(server.records().len(), 1);
+    let output = common::run_with_args("handles_address_loss_after_runs", &config, &args);
+    let _ = std::fs::remove_file(&state_file);
+
+    assert_succeeded(&output);
+    assert!(server.records().is_empty());
+}
+
+#[test]
+fn updates_https_hints_keeping_other_params() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+    server.add_data_record(
+        "www.example.com",
+        "HTTPS",
+        serde_json::json!({
+            "priority": 1,
+            "target": ".",
+            "value": "alpn=\"h3,h2\" ipv4hint=\"198.51.100.1\" ech=\"AEX+\"",
+        }),
+    );
+
+    let output = common::run(
+        "updates_https_hints_keeping_other_params",
+        &config(&server, API_TOKEN, true)
+            .replace("record_type = \"A\"", "record_type = [\"A\", \"HTTPS\"]"),
+    );
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.data("www.example.com", "HTTPS"),
+        [serde_json::json!({
+            "priority": 1,
+            "target": ".",
+            "value": "alpn=\"h3,h2\" ipv4hint=\"198.51.100.7\" ech=\"AEX+\"",
+        })]
+    );
+}
+
+/// The config of `config`, with `www.example.com` publishing the addresses of the ip sources `wan1` and `wan2` as a
+/// set of A records.
+fn record_set_config(server: &FakeCloudflare) -> String {
+    format!(
+        "{}{}{}",
+        server.ip_source("wan1"),
+        server.ip_source("wan2"),
+        config(server, API_TOKEN, true).replace(
+            "{ name = \"www\" }",
+            "{ name = \"www\", ip_sources = [\"wan1\", \"wan2\"] }",
+        )
+    )
+}
+
+#[test]
+fn publishes_record_set() {
+    let server = FakeCloudflare::start();
+    server.set_source_address("wan1", "198.51.100.1");
+    server.set_source_address("wan2", "198.51.100.2");
+    server.add_record("www.example.com", "A", "198.51.100.1");
+    server.add_record("www.example.com", "A", "198.51.100.9");
+    server.add_record("www.example.com", "A", "198.51.100.1");
+
+    let output = common::run("publishes_record_set", &record_set_config(&server));
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [
+            ("www.example.com".into(), "A".into(), "198.51.100.1".into()),
+            ("www.example.com".into(), "A".into(), "198.51.100.2".into()),
+        ]
+    );
+    // The stale record is reused and the duplicate deleted.
+    assert_eq!(server.requests("PUT").len(), 1);
+    assert!(server.requests("POST").is_empty());
+    assert_eq!(server.requests("DELETE").len(), 1);
+}
+
+#[test]
+fn keeps_stale_records_while_a_source_fails() {
+    let server = FakeCloudflare::start();
+    server.set_source_address("wan1", "198.51.100.1");
+    server.add_record("www.example.com", "A", "198.51.100.9");
+
+    let output = common::run(
+        "keeps_stale_records_while_a_source_fails",
+        &record_set_config(&server),
+    );
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [
+            ("www.example.com".into(), "A".into(), "198.51.100.1".into()),
+            ("www.example.com".into(), "A".into(), "198.51.100.9".into()),
+        ]
+    );
+    assert!(server.requests("PUT").is_empty());
+    assert!(server.requests("DELETE").is_empty());
+}
+
+/// The config of `config`, authenticated with the global api key `api_key` of the account of `common::EMAIL`.
+fn global_key_config(server: &FakeCloudflare, api_key: &str) -> String {
+    config(server, API_TOKEN, true).replace(
+        &server.service_provider(API_TOKEN),
+        &server.service_provider_with_key(common::EMAIL, api_key),
+    )
+}
+
+#[test]
+fn authenticates_with_global_key() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+
+    let output = common::run(
+        "authenticates_with_global_key",
+        &global_key_config(&server, common::API_KEY),
+    );
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [("www.example.com".into(), "A".into(), "198.51.100.7".into())]
+    );
+    assert!(server.requests("GET").iter().any(|r| r == "GET /user"));
+    assert!(!server
+        .requests("GET")
+        .iter()
+        .any(|r| r.starts_with("GET /user/tokens")));
+}
+
+#[test]
+fn rejected_global_key_changes_nothing() {
+    let server = FakeCloudflare::start();
+    server.set_address("198.51.100.7");
+
+    let output = common::run(
+        "rejected_global_key_changes_nothing",
+        &global_key_config(&server, "revoked-api-key"),
+    );
+
+    assert_succeeded(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown X-Auth-Key or X-Auth-Email"));
+    assert!(server.records().is_empty());
+    assert!(server.requests("POST").is_empty());
+}
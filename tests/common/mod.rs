@@ -0,0 +1,450 @@
+// Every test file uses a different part of the harness.
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The zone known by the fake server.
+pub const ZONE_NAME: &str = "example.com";
+pub const ZONE_ID: &str = "zone-id";
+/// The reverse zone of `198.51.100.0/24`, also known by the fake server.
+pub const REVERSE_ZONE_NAME: &str = "100.51.198.in-addr.arpa";
+pub const REVERSE_ZONE_ID: &str = "reverse-zone-id";
+/// The only api token accepted by the fake server.
+pub const API_TOKEN: &str = "valid-api-token";
+/// The only global api key accepted by the fake server, with the email of its account.
+pub const EMAIL: &str = "ddns@example.com";
+pub const API_KEY: &str = "valid-api-key";
+
+/// A fake cloudflare api on a local port, holding the records of a zone and its reverse zone in memory. Besides the
+/// api, `/ip` returns the address set by `set_address` and `/ip/<name>` the one set by `set_source_address`, so that
+/// url ip sources can be pointed at it.
+pub struct FakeCloudflare {
+    pub url: String,
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    records: Vec<Value>,
+    /// The method and path of every request received, without the query.
+    requests: Vec<String>,
+    next_id: u32,
+    address: String,
+    /// The addresses returned by `/ip/<name>`, by the name. Unknown names return an empty body.
+    source_addresses: HashMap<String, String>,
+}
+
+impl FakeCloudflare {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind fake cloudflare");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(FakeState::default()));
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = server_state.clone();
+                thread::spawn(move || handle_connection(stream, &state));
+            }
+        });
+        FakeCloudflare { url, state }
+    }
+
+    /// The address returned by `/ip`.
+    pub fn set_address(&self, address: &str) {
+        self.state.lock().unwrap().address = address.to_string();
+    }
+
+    /// The address returned by `/ip/<name>`.
+    pub fn set_source_address(&self, name: &str, address: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .source_addresses
+            .insert(name.to_string(), address.to_string());
+    }
+
+    /// Add an existing record to the zone and return its id.
+    pub fn add_record(&self, name: &str, record_type: &str, content: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        let id = state.new_id();
+        state.records.push(record(
+            &id,
+            &json!({ "name": name, "type": record_type, "content": content, "ttl": 1, "proxied": false }),
+        ));
+        id
+    }
+
+    /// Add an existing record with structured content, e.g. an HTTPS record, to the zone and return its id.
+    pub fn add_data_record(&self, name: &str, record_type: &str, data: Value) -> String {
+        let mut state = self.state.lock().unwrap();
+        let id = state.new_id();
+        state.records.push(record(
+            &id,
+            &json!({ "name": name, "type": record_type, "data": data, "ttl": 1 }),
+        ));
+        id
+    }
+
+    /// The structured content of every record of `name` and `record_type`.
+    pub fn data(&self, name: &str, record_type: &str) -> Vec<Value> {
+        let state = self.state.lock().unwrap();
+        state
+            .records
+            .iter()
+            .filter(|record| record["name"] == name && record["type"] == record_type)
+            .map(|record| record["data"].clone())
+            .collect()
+    }
+
+    /// Set the comment and tags of the record of `id`.
+    pub fn label_record(&self, id: &str, comment: &str, tags: &[&str]) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(record) = state.records.iter_mut().find(|record| record["id"] == id) {
+            record["comment"] = json!(comment);
+            record["tags"] = json!(tags);
+        }
+    }
+
+    /// The comment and tags of every record of `name`.
+    pub fn labels(&self, name: &str) -> Vec<(Option<String>, Vec<String>)> {
+        let state = self.state.lock().unwrap();
+        state
+            .records
+            .iter()
+            .filter(|record| record["name"] == name)
+            .map(|record| {
+                (
+                    record["comment"].as_str().map(str::to_string),
+                    serde_json::from_value(record["tags"].clone()).unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    /// The `(name, type, content)` of every record in the zone, sorted.
+    pub fn records(&self) -> Vec<(String, String, String)> {
+        let state = self.state.lock().unwrap();
+        let mut records: Vec<_> = state
+            .records
+            .iter()
+            .map(|record| {
+                (
+                    record["name"].as_str().unwrap_or_default().to_string(),
+                    record["type"].as_str().unwrap_or_default().to_string(),
+                    record["content"].as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+        records.sort();
+        records
+    }
+
+    /// The requests received with `method`, e.g. `"PUT"`.
+    pub fn requests(&self, method: &str) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .filter(|request| request.starts_with(&format!("{} ", method)))
+            .cloned()
+            .collect()
+    }
+
+    /// A url ip source named `ipv4` reading `/ip`, as the start of a config file.
+    pub fn ip_sources(&self) -> String {
+        format!(
+            "[ip_sources.ipv4]\ntype = \"url\"\nurl = \"{}/ip\"\n\n",
+            self.url
+        )
+    }
+
+    /// A url ip source named `name` reading `/ip/<name>`, as a table of a config file.
+    pub fn ip_source(&self, name: &str) -> String {
+        format!(
+            "[ip_sources.{}]\ntype = \"url\"\nurl = \"{}/ip/{}\"\n\n",
+            name, self.url, name
+        )
+    }
+
+    /// The service provider fields pointing at the fake server, with the zone id looked up by the domain name.
+    pub fn service_provider(&self, api_token: &str) -> String {
+        format!(
+            "{{ provider_name = \"cloudflare\", api_token = \"{}\", base_url = \"{}\" }}",
+            api_token, self.url
+        )
+    }
+
+    /// Like `service_provider`, with the global api key `api_key` of `email` instead of an api token.
+    pub fn service_provider_with_key(&self, email: &str, api_key: &str) -> String {
+        format!(
+            "{{ provider_name = \"cloudflare\", email = \"{}\", api_key = \"{}\", base_url = \"{}\" }}",
+            email, api_key, self.url
+        )
+    }
+}
+
+impl FakeState {
+    fn new_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("record-{}", self.next_id)
+    }
+}
+
+/// A record as returned by the api, with the fields of `fields` and the id `id`.
+fn record(id: &str, fields: &Value) -> Value {
+    let mut record = json!({
+        "id": id,
+        "name": "",
+        "type": "A",
+        "content": "",
+        "proxied": false,
+        "proxiable": true,
+        "ttl": 1,
+        "comment": null,
+        "tags": [],
+        "meta": null,
+        "created_on": "2024-01-01T00:00:00Z",
+        "modified_on": "2024-01-01T00:00:00Z",
+    });
+    for (key, value) in fields.as_object().into_iter().flatten() {
+        record[key] = value.clone();
+    }
+    record
+}
+
+fn success(result: Value) -> Value {
+    json!({ "result": result, "errors": [], "messages": [], "success": true })
+}
+
+fn failure(code: u32, message: &str) -> Value {
+    json!({ "result": null, "errors": [{ "code": code, "message": message }], "messages": [], "success": false })
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<FakeState>) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(stream) => stream,
+        Err(_) => return,
+    });
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut content_length = 0;
+    let mut authorization = String::new();
+    let mut auth_email = String::new();
+    let mut auth_key = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "authorization" => authorization = value.trim().to_string(),
+                "x-auth-email" => auth_email = value.trim().to_string(),
+                "x-auth-key" => auth_key = value.trim().to_string(),
+                _ => {}
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let mut words = request_line.split_whitespace();
+    let method = words.next().unwrap_or_default();
+    let target = words.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query: Vec<(&str, &str)> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .unwrap_or_default()
+    };
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(format!("{} {}", method, path));
+
+    if path == "/ip" {
+        let address = state.address.clone();
+        drop(state);
+        return respond(stream, "text/plain", &address);
+    }
+    if let Some(name) = path.strip_prefix("/ip/") {
+        let address = state
+            .source_addresses
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+        drop(state);
+        return respond(stream, "text/plain", &address);
+    }
+
+    let global_key = auth_email == EMAIL && auth_key == API_KEY;
+    let response = if !auth_email.is_empty() && !global_key {
+        failure(9103, "Unknown X-Auth-Key or X-Auth-Email")
+    } else if !global_key && authorization != format!("Bearer {}", API_TOKEN) {
+        failure(1000, "Invalid API Token")
+    } else {
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("GET", ["user"]) if global_key => success(json!({ "id": "user-id", "email": EMAIL })),
+            ("GET", ["user", "tokens", "verify"]) if !global_key => {
+                success(json!({ "id": "token-id", "status": "active" }))
+            }
+            // Like most tokens, the token cannot read its own policies.
+            ("GET", ["user", "tokens", _]) => {
+                failure(9109, "Unauthorized to access requested resource")
+            }
+            ("GET", ["zones"]) => {
+                let zones: Vec<Value> =
+                    [(ZONE_ID, ZONE_NAME), (REVERSE_ZONE_ID, REVERSE_ZONE_NAME)]
+                        .into_iter()
+                        .filter(|(_, name)| param("name") == *name)
+                        .map(|(id, name)| json!({ "id": id, "name": name }))
+                        .collect();
+                success(Value::Array(zones))
+            }
+            // The records of both zones are kept together, as their names do not overlap.
+            ("GET", ["zones", ZONE_ID | REVERSE_ZONE_ID, "dns_records"]) => {
+                let records: Vec<Value> = state
+                    .records
+                    .iter()
+                    .filter(|record| {
+                        record["name"] == param("name") && record["type"] == param("type")
+                    })
+                    .cloned()
+                    .collect();
+                let count = records.len();
+                let mut response = success(Value::Array(records));
+                response["result_info"] =
+                    json!({ "count": count, "page": 1, "per_page": 100, "total_count": count });
+                response
+            }
+            ("POST", ["zones", ZONE_ID | REVERSE_ZONE_ID, "dns_records"]) => {
+                let id = state.new_id();
+                let created = record(&id, &body);
+                state.records.push(created.clone());
+                success(created)
+            }
+            ("PUT", ["zones", ZONE_ID | REVERSE_ZONE_ID, "dns_records", id]) => {
+                match state.records.iter_mut().find(|record| record["id"] == *id) {
+                    Some(existing) => {
+                        *existing = record(id, &body);
+                        success(existing.clone())
+                    }
+                    None => failure(81044, "Record does not exist."),
+                }
+            }
+            ("DELETE", ["zones", ZONE_ID | REVERSE_ZONE_ID, "dns_records", id]) => {
+                let count = state.records.len();
+                state.records.retain(|record| record["id"] != *id);
+                if state.records.len() < count {
+                    success(json!({ "id": id }))
+                } else {
+                    failure(81044, "Record does not exist.")
+                }
+            }
+            _ => failure(7003, "Could not route to the path"),
+        }
+    };
+    drop(state);
+    respond(stream, "application/json", &response.to_string());
+}
+
+fn respond(mut stream: TcpStream, content_type: &str, body: &str) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    );
+}
+
+/// Run the script once with `config` as its toml config file.
+pub fn run(name: &str, config: &str) -> Output {
+    run_with_args(name, config, &[])
+}
+
+/// Run the script once with `config` as its toml config file and the extra arguments `args`.
+pub fn run_with_args(name: &str, config: &str, args: &[&str]) -> Output {
+    let path: PathBuf = std::env::temp_dir().join(format!(
+        "cloudflare-ddns-rust-{}-{}.toml",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, config).expect("write config file");
+    let output = Command::new(env!("CARGO_BIN_EXE_cloudflare-ddns-rust"))
+        .arg("--config")
+        .arg(&path)
+        .args(["--log-level", "debug"])
+        .args(args)
+        .output()
+        .expect("run cloudflare-ddns-rust");
+    let _ = std::fs::remove_file(&path);
+    output
+}
+
+/// Start a dns server on a local port answering every A query with `address`, and return its address.
+pub fn dns_stub(address: Ipv4Addr) -> String {
+    udp_stub(move |query| {
+        let mut answer = query.to_vec();
+        // Flags: answer with recursion desired and available, one answer.
+        answer[2..4].copy_from_slice(&0x8180u16.to_be_bytes());
+        answer[6..8].copy_from_slice(&1u16.to_be_bytes());
+        // The name points at the question, then type A, class IN, ttl and the address.
+        answer.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        answer.extend_from_slice(&address.octets());
+        answer
+    })
+}
+
+/// Start a STUN server on a local port answering every binding request with `address` in a XOR-MAPPED-ADDRESS,
+/// and return its address.
+pub fn stun_stub(address: Ipv4Addr) -> String {
+    udp_stub(move |request| {
+        let cookie = &request[4..8];
+        let mut response = vec![0x01, 0x01, 0, 12];
+        response.extend_from_slice(&request[4..20]);
+        // The family, the port and the address are xored with the magic cookie.
+        response.extend_from_slice(&[0, 0x20, 0, 8, 0, 1]);
+        response.extend_from_slice(&[0x0d ^ cookie[0], 0x96 ^ cookie[1]]);
+        response.extend(
+            address
+                .octets()
+                .iter()
+                .zip(cookie)
+                .map(|(octet, mask)| octet ^ mask),
+        );
+        response
+    })
+}
+
+/// Answer every datagram received on a local port with `respond`, and return the address of the port.
+fn udp_stub(respond: impl Fn(&[u8]) -> Vec<u8> + Send + 'static) -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind udp stub");
+    let address = socket.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let mut buffer = [0u8; 1500];
+        while let Ok((length, from)) = socket.recv_from(&mut buffer) {
+            let _ = socket.send_to(&respond(&buffer[..length]), from);
+        }
+    });
+    address
+}
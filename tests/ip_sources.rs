@@ -0,0 +1,133 @@
+mod common;
+
+use common::{FakeCloudflare, API_TOKEN};
+
+/// The config managing the A record of `www.example.com` with the address from the ip source `ip_source`.
+fn config(server: &FakeCloudflare, ip_source: &str) -> String {
+    format!(
+        "[ip_sources.ipv4]
+{}
+
+[[domain_settings]]
+enabled = true
+domain_name = \"example.com\"
+service_provider = {}
+record_type = \"A\"
+create_new_record = true
+allowed_ranges = [\"198.51.100.0/24\"]
+subdomains = [ {{ name = \"www\" }} ]
+",
+        ip_source,
+        server.service_provider(API_TOKEN)
+    )
+}
+
+fn assert_succeeded(output: &std::process::Output) {
+    assert!(
+        output.status.success(),
+        "run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn dns_source() {
+    let server = FakeCloudflare::start();
+    let resolver = common::dns_stub("198.51.100.9".parse().unwrap());
+
+    let output = common::run(
+        "dns_source",
+        &config(
+            &server,
+            &format!(
+                "type = \"dns\"\nname = \"myip.example\"\nresolver = \"{}\"",
+                resolver
+            ),
+        ),
+    );
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [("www.example.com".into(), "A".into(), "198.51.100.9".into())]
+    );
+}
+
+#[test]
+fn stun_source() {
+    let server = FakeCloudflare::start();
+    let stun_server = common::stun_stub("198.51.100.10".parse().unwrap());
+
+    let output = common::run(
+        "stun_source",
+        &config(
+            &server,
+            &format!("type = \"stun\"\nservers = [\"{}\"]", stun_server),
+        ),
+    );
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [("www.example.com".into(), "A".into(), "198.51.100.10".into())]
+    );
+}
+
+/// A command ip source running `script` with `sh`, killed after `timeout` seconds.
+#[cfg(unix)]
+fn command_source(script: &str, timeout: u64) -> String {
+    format!(
+        "type = \"command\"\ncommand = \"sh\"\nargs = [\"-c\", \"{}\"]\ntimeout = {}",
+        script, timeout
+    )
+}
+
+#[cfg(unix)]
+#[test]
+fn command_source_succeeding() {
+    let server = FakeCloudflare::start();
+
+    let output = common::run(
+        "command_source_succeeding",
+        &config(&server, &command_source("echo 198.51.100.11", 5)),
+    );
+
+    assert_succeeded(&output);
+    assert_eq!(
+        server.records(),
+        [("www.example.com".into(), "A".into(), "198.51.100.11".into())]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn command_source_failing() {
+    let server = FakeCloudflare::start();
+
+    let output = common::run(
+        "command_source_failing",
+        &config(&server, &command_source("echo 198.51.100.11; exit 3", 5)),
+    );
+
+    assert_succeeded(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("exited with exit status: 3"));
+    assert!(server.records().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn command_source_timeout() {
+    let server = FakeCloudflare::start();
+    let started = std::time::Instant::now();
+
+    // The background process keeps the output open after the command exits.
+    let output = common::run(
+        "command_source_timeout",
+        &config(&server, &command_source("sleep 15 & echo 198.51.100.11", 1)),
+    );
+
+    assert_succeeded(&output);
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not closed within 1 seconds"));
+    assert!(server.records().is_empty());
+}